@@ -0,0 +1,199 @@
+use std::io::{self, Read};
+
+use anyhow::{bail, Context, Result};
+use read_from::{BigEndian, ReadFrom};
+
+const MARKER_NUMBER: u8 = 0x00;
+const MARKER_BOOLEAN: u8 = 0x01;
+const MARKER_STRING: u8 = 0x02;
+const MARKER_OBJECT: u8 = 0x03;
+const MARKER_NULL: u8 = 0x05;
+const MARKER_UNDEFINED: u8 = 0x06;
+const MARKER_REFERENCE: u8 = 0x07;
+const MARKER_ECMA_ARRAY: u8 = 0x08;
+const MARKER_OBJECT_END: u8 = 0x09;
+const MARKER_STRICT_ARRAY: u8 = 0x0A;
+const MARKER_DATE: u8 = 0x0B;
+const MARKER_LONG_STRING: u8 = 0x0C;
+const MARKER_XML_DOCUMENT: u8 = 0x0F;
+const MARKER_TYPED_OBJECT: u8 = 0x10;
+
+/// How deeply objects and arrays may be nested, so that malformed files cannot overflow
+/// the stack.
+const MAX_DEPTH: usize = 64;
+
+/// A value encoded in Action Message Format 0.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Amf0Value {
+    Number(f64),
+    Boolean(bool),
+    String(String),
+    /// Objects and ECMA arrays, as properties in file order.
+    Object(Vec<(String, Amf0Value)>),
+    StrictArray(Vec<Amf0Value>),
+    Null,
+    Undefined,
+    /// An index into the previously read objects. References are not resolved.
+    Reference(u16),
+    /// Milliseconds since the unix epoch.
+    Date(f64),
+}
+
+impl Amf0Value {
+    /// Reads a single value, including its type marker.
+    pub fn read_from(input: &mut impl Read) -> Result<Amf0Value> {
+        Amf0Value::read_nested(input, 0)
+    }
+
+    /// Reads a value that is nested inside of `depth` objects or arrays.
+    fn read_nested(input: &mut impl Read, depth: usize) -> Result<Amf0Value> {
+        if depth > MAX_DEPTH {
+            bail!("amf0 values are nested more than {} deep", MAX_DEPTH);
+        }
+
+        let marker = u8::read_from(&mut *input).context("error while reading amf0 marker")?;
+
+        Ok(match marker {
+            MARKER_NUMBER => Amf0Value::Number(read_be(input, "number")?),
+            MARKER_BOOLEAN => Amf0Value::Boolean(
+                u8::read_from(&mut *input).context("error while reading boolean")? != 0,
+            ),
+            MARKER_STRING => Amf0Value::String(read_string(input)?),
+            MARKER_OBJECT => Amf0Value::Object(read_properties(input, depth + 1)?),
+            MARKER_NULL => Amf0Value::Null,
+            MARKER_UNDEFINED => Amf0Value::Undefined,
+            MARKER_REFERENCE => Amf0Value::Reference(read_be(input, "reference")?),
+            MARKER_ECMA_ARRAY => {
+                // the count is only a hint, the array is terminated like an object
+                let _: u32 = read_be(input, "ecma array length")?;
+                Amf0Value::Object(read_properties(input, depth + 1)?)
+            }
+            MARKER_STRICT_ARRAY => {
+                let len: u32 = read_be(input, "strict array length")?;
+                Amf0Value::StrictArray(
+                    (0..len)
+                        .map(|_| Amf0Value::read_nested(input, depth + 1))
+                        .collect::<Result<_>>()?,
+                )
+            }
+            MARKER_DATE => {
+                let millis = read_be(input, "date")?;
+                let _timezone: i16 = read_be(input, "date timezone")?;
+                Amf0Value::Date(millis)
+            }
+            MARKER_LONG_STRING | MARKER_XML_DOCUMENT => {
+                let len: u32 = read_be(input, "long string length")?;
+                Amf0Value::String(read_utf8(input, len as usize)?)
+            }
+            MARKER_TYPED_OBJECT => {
+                let _class_name = read_string(input)?;
+                Amf0Value::Object(read_properties(input, depth + 1)?)
+            }
+            other => bail!("unsupported amf0 marker {:#04x}", other),
+        })
+    }
+
+    /// Gets a property of an object, or an element of an array by its stringified index.
+    pub fn get(&self, key: &str) -> Option<&Amf0Value> {
+        match self {
+            Amf0Value::Object(properties) => properties
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            Amf0Value::StrictArray(values) => values.get(key.parse::<usize>().ok()?),
+            _ => None,
+        }
+    }
+
+    /// Returns the values of an array, or of an object in property order.
+    pub fn elements(&self) -> Vec<&Amf0Value> {
+        match self {
+            Amf0Value::Object(properties) => properties.iter().map(|(_, value)| value).collect(),
+            Amf0Value::StrictArray(values) => values.iter().collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Converts numbers, numeric strings and booleans to a number.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Amf0Value::Number(n) => Some(*n),
+            Amf0Value::String(s) => s.trim().parse().ok(),
+            Amf0Value::Boolean(b) => Some(*b as u8 as f64),
+            _ => None,
+        }
+    }
+
+    /// Converts booleans and numbers to a boolean.
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Amf0Value::Boolean(b) => Some(*b),
+            Amf0Value::Number(n) => Some(*n != 0.0),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Amf0Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn is_missing(&self) -> bool {
+        matches!(self, Amf0Value::Null | Amf0Value::Undefined)
+    }
+}
+
+/// Reads properties until the empty name followed by the object end marker.
+fn read_properties(input: &mut impl Read, depth: usize) -> Result<Vec<(String, Amf0Value)>> {
+    let mut properties = vec![];
+    loop {
+        let name = read_string(input)?;
+        if name.is_empty() {
+            let marker = u8::read_from(&mut *input).context("error while reading object end")?;
+            if marker == MARKER_OBJECT_END {
+                return Ok(properties);
+            }
+            bail!("expected amf0 object end, found marker {:#04x}", marker);
+        }
+
+        let value = Amf0Value::read_nested(input, depth)
+            .with_context(|| format!("error while reading property {name:?}"))?;
+        properties.push((name, value));
+    }
+}
+
+pub(crate) fn read_be<T>(input: &mut impl Read, what: &str) -> Result<T>
+where
+    BigEndian<T>: ReadFrom<Error = io::Error>,
+{
+    Ok(BigEndian::<T>::read_from(input)
+        .with_context(|| format!("error while reading {what}"))?
+        .0)
+}
+
+/// Reads a UTF-8 string prefixed by its length as a big-endian `u16`.
+pub(crate) fn read_string(input: &mut impl Read) -> Result<String> {
+    let len: u16 = read_be(input, "string length")?;
+    read_utf8(input, len as usize)
+}
+
+/// Reads `len` bytes of UTF-8. The buffer only grows as bytes are read, so a corrupt length
+/// cannot allocate more memory than the input has left.
+fn read_utf8(input: &mut impl Read, len: usize) -> Result<String> {
+    let mut buf = vec![];
+    input
+        .take(len as u64)
+        .read_to_end(&mut buf)
+        .context("error while reading string")?;
+    if buf.len() != len {
+        bail!(
+            "string is {} bytes long, but only {} bytes are left",
+            len,
+            buf.len()
+        );
+    }
+
+    String::from_utf8(buf).context("string is not valid utf-8")
+}
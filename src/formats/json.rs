@@ -0,0 +1,331 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::game::{
+    Line, LineBuilder, LineType, PhysicsVersion, Track, TrackInfo, TrackMeta, Vector2D,
+    DEFAULT_SCENERY_WIDTH,
+};
+use crate::rider::{Entity, PointIndex, RiderOptions, DEFAULT_START_VELOCITY};
+
+/// Reads a linerider.com `.track.json` file into a [`Track`].
+pub fn read(json: &str) -> Result<Track> {
+    let json_track: JsonTrack =
+        serde_json::from_str(json).context("error while parsing track json")?;
+
+    // tracks without `riders` start a single rider at `startPosition`, while an empty list
+    // is a track without riders
+    let riders = match &json_track.riders {
+        Some(riders) => riders.iter().map(JsonRider::to_entity).collect(),
+        None => vec![Entity::starting_boshsled(
+            json_track.start_position.to_vector(),
+            DEFAULT_START_VELOCITY,
+            0.0,
+        )],
+    };
+    // linerider.com's own fields win over `boshMeta`, which may be stale if another editor
+    // changed the track
+    let mut meta = json_track.bosh_meta.unwrap_or_default();
+    if let Some(version) = json_track.version.as_deref() {
+        meta.physics_version = PhysicsVersion::from_tag(version)
+            .with_context(|| format!("unknown version {version:?}"))?;
+    }
+    meta.frictionless = json_track.frictionless;
+    meta.validate().context("invalid boshMeta")?;
+    let mut track = Track::new_with_meta(riders, vec![], meta);
+
+    track.info = TrackInfo {
+        title: json_track.label,
+        creator: json_track.creator,
+        description: json_track.description,
+        duration: json_track.duration,
+        version: json_track.version,
+        song: None,
+    };
+
+    for json_line in &json_track.lines {
+        let line = json_line.to_line(track.line_builder())?;
+        track.add_line(line)?;
+    }
+
+    Ok(track)
+}
+
+/// Writes a [`Track`] as a linerider.com `.track.json` file.
+///
+/// Riders are written from the sled pegs of the starting entities, and an error is returned
+/// for entities that are not riders in a starting pose, which linerider.com cannot start
+/// with. The track's [`TrackMeta`] is stored under `boshMeta` and each rider's
+/// [`RiderOptions`] under `boshOptions`, so that [`read`] can restore them.
+///
+/// linerider.com only rides 6.1 and 6.2 physics, so tracks with other physics versions are
+/// refused.
+pub fn write(track: &Track) -> Result<String> {
+    let version = track.physics_version();
+    if !matches!(version, PhysicsVersion::SixOne | PhysicsVersion::SixTwo) {
+        bail!(
+            "linerider.com cannot ride tracks with {} physics",
+            version.tag()
+        );
+    }
+    let riders = track
+        .entity_positions_at(0)
+        .iter()
+        .enumerate()
+        .map(|(i, entity)| JsonRider::from_entity(entity).with_context(|| format!("rider {i}")))
+        .collect::<Result<Vec<_>>>()?;
+    let start = riders
+        .first()
+        .map(|rider| rider.start_position.to_vector())
+        .unwrap_or_default();
+
+    let json_track = JsonTrack {
+        label: track.info.title.clone(),
+        creator: track.info.creator.clone(),
+        description: track.info.description.clone(),
+        duration: track.info.duration,
+        version: Some(version.tag().to_string()),
+        start_position: JsonVector::from_vector(start),
+        riders: Some(riders),
+        frictionless: track.frictionless(),
+        lines: track.all_lines().iter().map(JsonLine::from_line).collect(),
+        bosh_meta: Some(*track.meta()),
+    };
+
+    serde_json::to_string(&json_track).context("error while writing track json")
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct JsonTrack {
+    #[serde(default)]
+    label: String,
+    #[serde(default)]
+    creator: String,
+    #[serde(default)]
+    description: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    duration: Option<u64>,
+    #[serde(default)]
+    version: Option<String>,
+    #[serde(default)]
+    start_position: JsonVector,
+    #[serde(default)]
+    riders: Option<Vec<JsonRider>>,
+    #[serde(
+        default,
+        deserialize_with = "bool_or_int",
+        skip_serializing_if = "std::ops::Not::not"
+    )]
+    frictionless: bool,
+    lines: Vec<JsonLine>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bosh_meta: Option<TrackMeta>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct JsonVector {
+    x: f64,
+    y: f64,
+}
+
+impl JsonVector {
+    fn to_vector(&self) -> Vector2D {
+        Vector2D(self.x, self.y)
+    }
+
+    fn from_vector(vector: Vector2D) -> JsonVector {
+        JsonVector {
+            x: vector.0,
+            y: vector.1,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct JsonRider {
+    start_position: JsonVector,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    start_velocity: Option<JsonVector>,
+    /// Clockwise rotation around the sled peg, in degrees.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    start_angle: Option<f64>,
+    #[serde(default, deserialize_with = "bool_or_int")]
+    remountable: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bosh_options: Option<RiderOptions>,
+}
+
+impl JsonRider {
+    fn to_entity(&self) -> Entity {
+        let velocity = self
+            .start_velocity
+            .as_ref()
+            .map_or(DEFAULT_START_VELOCITY, JsonVector::to_vector);
+        let rotation = self.start_angle.unwrap_or(0.0).to_radians();
+        let mut entity =
+            Entity::starting_boshsled(self.start_position.to_vector(), velocity, rotation);
+        entity.options = RiderOptions {
+            remountable: self.remountable,
+            ..self.bosh_options.unwrap_or_default()
+        };
+        entity
+    }
+
+    /// Returns an error for entities that [`JsonRider::to_entity`] would not read back,
+    /// such as a lone bosh or a rider that has already moved.
+    fn from_entity(entity: &Entity) -> Result<JsonRider> {
+        let peg = entity
+            .points
+            .get(&PointIndex::SledPeg)
+            .context("linerider.com riders need a sled")?;
+        let start_angle = entity.points.get(&PointIndex::SledRope).map(|rope| {
+            let direction = rope.location - peg.location;
+            direction.1.atan2(direction.0).to_degrees()
+        });
+        let rider = JsonRider {
+            start_position: JsonVector::from_vector(peg.location),
+            start_velocity: Some(JsonVector::from_vector(peg.momentum)),
+            start_angle,
+            remountable: entity.options.remountable,
+            bosh_options: Some(entity.options),
+        };
+
+        if !rider.reads_back_as(entity) {
+            bail!("linerider.com cannot represent a rider that is not in its starting pose");
+        }
+        Ok(rider)
+    }
+
+    /// Whether [`JsonRider::to_entity`] reads `self` back as `entity`. The start angle is
+    /// written in degrees, so points may move by rounding errors.
+    fn reads_back_as(&self, entity: &Entity) -> bool {
+        let read = self.to_entity();
+        let close = |a: Vector2D, b: Vector2D| (a - b).length_squared() < 1e-18;
+        read.bones == entity.bones
+            && read.joints == entity.joints
+            && read.options == entity.options
+            && read.mount_state == entity.mount_state
+            && read.points.len() == entity.points.len()
+            && read.points.iter().all(|(index, read)| {
+                entity.points.get(index).is_some_and(|point| {
+                    read.friction == point.friction
+                        && close(read.location, point.location)
+                        && close(read.previous_location, point.previous_location)
+                        && close(read.momentum, point.momentum)
+                })
+            })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct JsonLine {
+    id: i64,
+    #[serde(rename = "type")]
+    line_type: u8,
+    x1: f64,
+    y1: f64,
+    x2: f64,
+    y2: f64,
+    #[serde(default, deserialize_with = "bool_or_int")]
+    flipped: bool,
+    /// Bitfield where `1` extends the first point and `2` extends the second.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    extended: Option<u8>,
+    #[serde(default, deserialize_with = "bool_or_int")]
+    left_extended: bool,
+    #[serde(default, deserialize_with = "bool_or_int")]
+    right_extended: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    multiplier: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    width: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    left_line: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    right_line: Option<i64>,
+}
+
+impl JsonLine {
+    fn to_line(&self, builder: LineBuilder) -> Result<Line> {
+        let line_type = match self.line_type {
+            0 => LineType::Normal,
+            1 => {
+                let multiplier = self.multiplier.unwrap_or(1.0);
+                if !multiplier.is_finite() || multiplier < 0.0 {
+                    bail!(
+                        "line {} has unsupported acceleration multiplier {}",
+                        self.id,
+                        multiplier
+                    );
+                }
+                LineType::Accelerate { amount: multiplier }
+            }
+            2 => LineType::Scenery {
+                width: self.width.unwrap_or(DEFAULT_SCENERY_WIDTH),
+            },
+            other => bail!("line {} has unknown line type {}", self.id, other),
+        };
+
+        let (left_extended, right_extended) = match self.extended {
+            Some(bits) => (bits & 1 != 0, bits & 2 != 0),
+            None => (self.left_extended, self.right_extended),
+        };
+
+        Ok(builder
+            .id(self.id)
+            .line_type(line_type)
+            .flipped(self.flipped)
+            .left_line(self.left_line)
+            .right_line(self.right_line)
+            .point(self.x1, self.y1)
+            .extended(left_extended)
+            .point(self.x2, self.y2)
+            .extended(right_extended)
+            .build())
+    }
+
+    fn from_line(line: &Line) -> JsonLine {
+        let (line_type, multiplier, width) = match line.line_type {
+            LineType::Normal => (0, None, None),
+            LineType::Accelerate { amount } => (1, Some(amount), None),
+            LineType::Scenery { width } => (2, None, Some(width)),
+        };
+
+        JsonLine {
+            id: line.id,
+            line_type,
+            x1: line.ends.0.location.0,
+            y1: line.ends.0.location.1,
+            x2: line.ends.1.location.0,
+            y2: line.ends.1.location.1,
+            flipped: line.flipped,
+            extended: None,
+            left_extended: line.ends.0.extended,
+            right_extended: line.ends.1.extended,
+            multiplier,
+            width,
+            left_line: line.left_line,
+            right_line: line.right_line,
+        }
+    }
+}
+
+/// Older tracks store booleans as `0`/`1`, newer tracks use `true`/`false`. `null` reads
+/// as `false`.
+fn bool_or_int<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum BoolOrInt {
+        Bool(bool),
+        Int(i64),
+        Null(()),
+    }
+
+    Ok(match BoolOrInt::deserialize(deserializer)? {
+        BoolOrInt::Bool(b) => b,
+        BoolOrInt::Int(i) => i != 0,
+        BoolOrInt::Null(()) => false,
+    })
+}
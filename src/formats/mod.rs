@@ -0,0 +1,1270 @@
+mod amf0;
+pub mod json;
+pub mod recording;
+pub mod sol;
+pub mod svg;
+pub mod trk;
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::formats::{json, recording, sol, svg, trk};
+    use crate::rider::{
+        Bone, Entity, MountState, PointIndex, RiderOptions, DEFAULT_START_VELOCITY,
+    };
+    use crate::{Line, LineType, PhysicsVersion, Song, Track, TrackInfo, TrackMeta, Vector2D};
+
+    #[test]
+    fn json_extended_bitfield() {
+        let track = json::read(
+            &fs::read_to_string("./fixtures/legacyTestTrack.track.json")
+                .expect("Failed to read file"),
+        )
+        .expect("Failed to parse file");
+
+        let line = track
+            .all_lines()
+            .iter()
+            .find(|l| l.id == 6815)
+            .expect("line 6815 should exist");
+        assert!(line.ends.0.extended && line.ends.1.extended);
+        assert_eq!(line.line_type, LineType::Accelerate { amount: 1.0 });
+    }
+
+    #[test]
+    fn json_extended_booleans() {
+        let track = json::read(
+            r#"{
+                "label": "",
+                "version": "6.2",
+                "startPosition": { "x": 0, "y": 0 },
+                "lines": [
+                    { "id": 1, "type": 0, "x1": 0, "y1": 0, "x2": 10, "y2": 0,
+                      "flipped": true, "leftExtended": false, "rightExtended": true }
+                ]
+            }"#,
+        )
+        .expect("Failed to parse track");
+
+        let line = track.all_lines().first().expect("line should exist");
+        assert!(line.flipped);
+        assert!(!line.ends.0.extended);
+        assert!(line.ends.1.extended);
+
+        // older tracks store these booleans as integers, or leave them null
+        let track = json::read(
+            r#"{
+                "label": "",
+                "startPosition": { "x": 0, "y": 0 },
+                "lines": [
+                    { "id": 1, "type": 0, "x1": 0, "y1": 0, "x2": 10, "y2": 0,
+                      "flipped": 1, "leftExtended": 1, "rightExtended": null }
+                ]
+            }"#,
+        )
+        .expect("Failed to parse track");
+
+        let line = track.all_lines().first().expect("line should exist");
+        assert!(line.flipped);
+        assert!(line.ends.0.extended);
+        assert!(!line.ends.1.extended);
+    }
+
+    #[test]
+    fn json_start_position() {
+        let track = json::read(
+            &fs::read_to_string("./fixtures/cycloid.track.json").expect("Failed to read file"),
+        )
+        .expect("Failed to parse file");
+
+        let entities = track.entity_positions_at(0);
+        let rider = entities.first().expect("rider should exist");
+        assert_eq!(
+            rider.point_at(PointIndex::SledPeg).location,
+            Vector2D(514.55, 280.35)
+        );
+    }
+
+    #[test]
+    fn json_errors() {
+        assert!(json::read("not json").is_err());
+        assert!(json::read(r#"{ "lines": [ { "id": 0 } ] }"#).is_err());
+        assert!(json::read(
+            r#"{ "lines": [ { "id": 0, "type": 7, "x1": 0, "y1": 0, "x2": 1, "y2": 1 } ] }"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn json_round_trip() {
+        for fixture in [
+            "./fixtures/crash.track.json",
+            "./fixtures/cycloid.track.json",
+            "./fixtures/legacyTestTrack.track.json",
+            "./fixtures/testTrack.track.json",
+        ] {
+            let track = json::read(&fs::read_to_string(fixture).expect("Failed to read file"))
+                .expect("Failed to parse file");
+            let written = json::write(&track).expect("Failed to write track");
+            let reread = json::read(&written).expect("Failed to parse written track");
+
+            assert_eq!(track.all_lines(), reread.all_lines(), "{fixture}");
+            assert_eq!(
+                track.entity_positions_at(200),
+                reread.entity_positions_at(200),
+                "{fixture}"
+            );
+        }
+    }
+
+    #[test]
+    fn json_riders() {
+        let track = json::read(
+            r#"{
+                "startPosition": { "x": 0, "y": 0 },
+                "riders": [
+                    { "startPosition": { "x": 5, "y": 6 }, "remountable": 1 },
+                    { "startPosition": { "x": -5, "y": 0 }, "remountable": false },
+                    { "startPosition": { "x": 10, "y": 0 }, "startVelocity": { "x": 0, "y": 0 } }
+                ],
+                "lines": []
+            }"#,
+        )
+        .expect("Failed to parse track");
+
+        let entities = track.entity_positions_at(0);
+        assert_eq!(entities.len(), 3);
+        assert_eq!(
+            entities[0].point_at(PointIndex::SledPeg).location,
+            Vector2D(5.0, 6.0)
+        );
+        assert!(entities[0].options.remountable);
+        assert!(!entities[1].options.remountable);
+        assert_eq!(
+            entities[0].point_at(PointIndex::SledPeg).momentum,
+            DEFAULT_START_VELOCITY
+        );
+        assert_eq!(
+            entities[2].point_at(PointIndex::SledPeg).momentum,
+            Vector2D(0.0, 0.0)
+        );
+        assert_eq!(
+            entities[2].point_at(PointIndex::SledNose).previous_location,
+            Vector2D(25.0, 5.0)
+        );
+
+        let reread = json::read(&json::write(&track).expect("Failed to write track"))
+            .expect("Failed to parse written track");
+        assert_eq!(reread.entity_positions_at(0), entities);
+    }
+
+    #[test]
+    fn json_rider_options() {
+        let mut rider = Entity::starting_boshsled(Vector2D(3.0, 4.0), DEFAULT_START_VELOCITY, 0.5);
+        rider.options = RiderOptions {
+            remountable: true,
+            gravity_scale: 0.5,
+            mount_endurance_scale: 2.0,
+            invincible: true,
+        };
+        let track = Track::new(vec![rider.clone()], vec![]);
+
+        let written = json::write(&track).expect("Failed to write track");
+        let value: serde_json::Value =
+            serde_json::from_str(&written).expect("Failed to parse written track");
+        let start_angle = value["riders"][0]["startAngle"].as_f64().unwrap();
+        assert!((start_angle - 0.5f64.to_degrees()).abs() < 1e-9);
+
+        let reread = json::read(&written).expect("Failed to parse written track");
+        let entities = reread.entity_positions_at(0);
+        assert_eq!(entities[0].options, rider.options);
+        for (index, point) in &rider.points {
+            let error = entities[0].point_at(*index).location - point.location;
+            assert!(error.length_squared() < 1e-20, "{index:?}");
+        }
+    }
+
+    #[test]
+    fn json_write_errors() {
+        let bosh = Track::new(vec![Entity::default_bosh()], vec![]);
+        assert!(json::write(&bosh).is_err());
+
+        let mut moved = Entity::starting_boshsled(Vector2D(0.0, 0.0), DEFAULT_START_VELOCITY, 0.0);
+        moved.point_at_mut(PointIndex::BoshShoulder).location = Vector2D(40.0, 40.0);
+        assert!(json::write(&Track::new(vec![moved], vec![])).is_err());
+
+        let track = json::read(r#"{ "lines": [] }"#).expect("Failed to parse track");
+        let written = json::write(&track).expect("Failed to write track");
+        assert!(!written.contains("duration"));
+
+        let mut six_zero = track.clone();
+        six_zero
+            .set_physics_version(PhysicsVersion::SixZero)
+            .unwrap();
+        assert!(json::write(&six_zero).is_err());
+    }
+
+    #[test]
+    fn json_no_riders() {
+        let track = Track::new(vec![], vec![]);
+        let written = json::write(&track).expect("Failed to write track");
+        let value: serde_json::Value =
+            serde_json::from_str(&written).expect("Failed to parse written track");
+        assert_eq!(value["riders"], serde_json::json!([]));
+
+        let reread = json::read(&written).expect("Failed to parse written track");
+        assert!(reread.entity_positions_at(0).is_empty());
+    }
+
+    #[test]
+    fn json_bosh_meta_keys() {
+        let track = json::read(r#"{ "lines": [] }"#).expect("Failed to parse track");
+        let written = json::write(&track).expect("Failed to write track");
+        let value: serde_json::Value =
+            serde_json::from_str(&written).expect("Failed to parse written track");
+        assert_eq!(value["boshMeta"]["cellSize"], 14.0);
+        assert_eq!(value["boshMeta"]["physicsVersion"], "SixTwo");
+
+        // tracks written before the keys were camelCase
+        let track = json::read(
+            r#"{
+                "lines": [],
+                "boshMeta": {
+                    "line_extension_ratio": 0.5, "gravity_well_height": 10, "cell_size": 20,
+                    "physics_version": "SixOne"
+                }
+            }"#,
+        )
+        .expect("Failed to parse track");
+        assert_eq!(track.cell_size(), 20.0);
+        assert_eq!(track.line_extension_ratio(), 0.5);
+        assert_eq!(track.physics_version(), PhysicsVersion::SixOne);
+    }
+
+    #[test]
+    fn json_top_level_fields_win() {
+        let track = json::read(
+            r#"{
+                "version": "6.0",
+                "riders": [
+                    { "startPosition": { "x": 0, "y": 0 }, "remountable": false,
+                      "boshOptions": { "remountable": true, "gravityScale": 2 } }
+                ],
+                "lines": [],
+                "boshMeta": {
+                    "lineExtensionRatio": 0.25, "gravityWellHeight": 10, "cellSize": 14,
+                    "physicsVersion": "SixOne", "frictionless": true, "iterations": 3
+                }
+            }"#,
+        )
+        .expect("Failed to parse track");
+
+        assert_eq!(track.physics_version(), PhysicsVersion::SixZero);
+        assert!(!track.frictionless());
+        assert_eq!(track.iterations(), 3);
+        let options = track.entity_positions_at(0)[0].options;
+        assert!(!options.remountable);
+        assert_eq!(options.gravity_scale, 2.0);
+    }
+
+    #[test]
+    fn json_frictionless() {
+        let track =
+            json::read(r#"{ "frictionless": 1, "lines": [] }"#).expect("Failed to parse track");
+        assert!(track.frictionless());
+
+        let written = json::write(&track).expect("Failed to write track");
+        let mut value: serde_json::Value =
+            serde_json::from_str(&written).expect("Failed to parse written track");
+        assert_eq!(value["frictionless"], true);
+        value.as_object_mut().unwrap().remove("boshMeta");
+        let reread = json::read(&value.to_string()).expect("Failed to parse written track");
+        assert!(reread.frictionless());
+
+        let track = json::read(r#"{ "lines": [] }"#).expect("Failed to parse track");
+        assert!(!track.frictionless());
+        let written = json::write(&track).expect("Failed to write track");
+        assert!(!written.contains("\"frictionless\":true"));
+    }
+
+    #[test]
+    fn json_fractional_multiplier() {
+        let track = json::read(
+            r#"{
+                "lines": [
+                    { "id": 1, "type": 1, "x1": 0, "y1": 0, "x2": 10, "y2": 0, "multiplier": 0.5 },
+                    { "id": 2, "type": 1, "x1": 0, "y1": 5, "x2": 10, "y2": 5, "multiplier": 0 },
+                    { "id": 3, "type": 1, "x1": 0, "y1": 9, "x2": 10, "y2": 9, "multiplier": 2 }
+                ]
+            }"#,
+        )
+        .expect("Failed to parse track");
+
+        let amounts: Vec<_> = track
+            .all_lines()
+            .iter()
+            .map(|line| line.line_type)
+            .collect();
+        assert_eq!(
+            amounts,
+            vec![
+                LineType::Accelerate { amount: 0.5 },
+                LineType::Accelerate { amount: 0.0 },
+                LineType::Accelerate { amount: 2.0 },
+            ]
+        );
+
+        let reread = json::read(&json::write(&track).expect("Failed to write track"))
+            .expect("Failed to parse written track");
+        assert_eq!(track.all_lines(), reread.all_lines());
+
+        assert!(json::read(
+            r#"{ "lines": [ { "id": 0, "type": 1, "x1": 0, "y1": 0, "x2": 1, "y2": 1, "multiplier": -1 } ] }"#
+        )
+        .is_err());
+
+        let mut zero_cells: serde_json::Value =
+            serde_json::from_str(&json::write(&track).expect("Failed to write track")).unwrap();
+        zero_cells["boshMeta"]["cellSize"] = 0.0.into();
+        assert!(json::read(&zero_cells.to_string()).is_err());
+
+        let long_six_zero = r#"{ "version": "6.0", "lines": [
+            { "id": 0, "type": 0, "x1": 0, "y1": 0, "x2": 200000, "y2": 200000 }
+        ] }"#;
+        assert!(json::read(long_six_zero).is_err());
+        assert!(json::read(&long_six_zero.replace("6.0", "6.2")).is_ok());
+        assert!(json::read(&long_six_zero.replace("6.0", "5.9")).is_err());
+    }
+
+    #[test]
+    fn json_coordinate_errors() {
+        let line_to = |x2: &str| {
+            format!(
+                r#"{{ "lines": [ {{ "id": 0, "type": 0, "x1": 0, "y1": 0, "x2": {x2}, "y2": 0 }} ] }}"#
+            )
+        };
+        for x2 in ["1e9", "1e12", "1e300", "1e308", "1e999"] {
+            assert!(json::read(&line_to(x2)).is_err(), "{x2}");
+        }
+        assert!(json::read(&line_to("1e5")).is_ok());
+
+        // json cannot spell out non-finite numbers, but other readers can produce them
+        let meta = TrackMeta::default();
+        for x2 in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            let line = Line::builder().point(0.0, 0.0).point(x2, 0.0).build();
+            assert!(meta.validate_line(&line).is_err(), "{x2}");
+        }
+    }
+
+    fn trk_string(bytes: &mut Vec<u8>, s: &str) {
+        bytes.extend((s.len() as i16).to_le_bytes());
+        bytes.extend(s.as_bytes());
+    }
+
+    fn trk_vector(bytes: &mut Vec<u8>, x: f64, y: f64) {
+        bytes.extend(x.to_le_bytes());
+        bytes.extend(y.to_le_bytes());
+    }
+
+    #[test]
+    fn trk_read_all_features() {
+        let mut bytes = vec![b'T', b'R', b'K', 0xF2, 1];
+        trk_string(
+            &mut bytes,
+            "REDMULTIPLIER;SCENERYWIDTH;6.1;SONGINFO;IGNORABLE_TRIGGER;ZEROSTART;REMOUNT;FRICTIONLESS;",
+        );
+        let song = "song name\r\n1.5";
+        bytes.push(song.len() as u8);
+        bytes.extend(song.as_bytes());
+        trk_vector(&mut bytes, 10.0, -20.0);
+        bytes.extend(3i32.to_le_bytes());
+
+        // flipped acceleration line, extended at both ends, with a zoom trigger
+        bytes.extend([0x80 | 0x60 | 2, 3, 1]);
+        bytes.extend(2.0f32.to_le_bytes());
+        bytes.extend(40i16.to_le_bytes());
+        bytes.extend(7i32.to_le_bytes());
+        bytes.extend(6i32.to_le_bytes());
+        bytes.extend(8i32.to_le_bytes());
+        trk_vector(&mut bytes, 0.0, 0.0);
+        trk_vector(&mut bytes, 30.0, 5.0);
+
+        // normal line without a trigger
+        bytes.extend([1, 0]);
+        bytes.extend(8i32.to_le_bytes());
+        trk_vector(&mut bytes, 30.0, 5.0);
+        trk_vector(&mut bytes, 60.0, 5.0);
+
+        // scenery line with a width
+        bytes.extend([0, 20]);
+        trk_vector(&mut bytes, 0.0, 0.0);
+        trk_vector(&mut bytes, -30.0, 0.0);
+
+        bytes.extend(b"META");
+        bytes.extend(1i16.to_le_bytes());
+        trk_string(&mut bytes, "STARTZOOM=4");
+
+        let file = trk::read(&bytes).expect("Failed to parse trk");
+        assert_eq!(
+            file.features,
+            trk::TrkFeatures {
+                red_multiplier: true,
+                scenery_width: true,
+                six_one: true,
+                song_info: true,
+                ignorable_trigger: true,
+                zero_start: true,
+                remount: true,
+                frictionless: true,
+            }
+        );
+        assert_eq!(
+            file.track.info.song,
+            Some(Song {
+                name: "song name".to_string(),
+                offset: 1.5,
+            })
+        );
+        assert_eq!(file.track.info.version.as_deref(), Some("6.1"));
+        assert!(file.track.frictionless());
+        assert_eq!(
+            file.metadata,
+            vec![("STARTZOOM".to_string(), "4".to_string())]
+        );
+
+        let lines = file.track.all_lines();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].id, 7);
+        assert_eq!(lines[0].line_type, LineType::Accelerate { amount: 3.0 });
+        assert!(lines[0].flipped && lines[0].ends.0.extended && lines[0].ends.1.extended);
+        assert_eq!(lines[1].id, 8);
+        assert_eq!(lines[1].line_type, LineType::Normal);
+        assert!(!lines[1].flipped && !lines[1].ends.0.extended);
+        assert_eq!(lines[2].line_type, LineType::Scenery { width: 2.0 });
+        assert_eq!(lines[2].ends.1.location, Vector2D(-30.0, 0.0));
+
+        let entities = file.track.entity_positions_at(0);
+        let peg = entities
+            .first()
+            .expect("rider should exist")
+            .point_at(PointIndex::SledPeg);
+        assert_eq!(peg.location, Vector2D(10.0, -20.0));
+        assert_eq!(peg.previous_location, peg.location);
+    }
+
+    #[test]
+    fn trk_errors() {
+        assert!(trk::read(b"not a trk").is_err());
+
+        let mut unsupported_feature = vec![b'T', b'R', b'K', 0xF2, 1];
+        trk_string(&mut unsupported_feature, "TIMETRAVEL;");
+        assert!(trk::read(&unsupported_feature).is_err());
+
+        let mut truncated = vec![b'T', b'R', b'K', 0xF2, 1];
+        trk_string(&mut truncated, "");
+        trk_vector(&mut truncated, 0.0, 0.0);
+        truncated.extend(1i32.to_le_bytes());
+        truncated.push(1);
+        assert!(trk::read(&truncated).is_err());
+
+        let mut oversized_song = vec![b'T', b'R', b'K', 0xF2, 1];
+        trk_string(&mut oversized_song, "SONGINFO;");
+        oversized_song.extend([0xFF, 0xFF, 0xFF, 0xFF, 0x7F]);
+        assert!(trk::read(&oversized_song).is_err());
+
+        let mut truncated_song = vec![b'T', b'R', b'K', 0xF2, 1];
+        trk_string(&mut truncated_song, "SONGINFO;");
+        truncated_song.push(20);
+        truncated_song.extend(b"song");
+        assert!(trk::read(&truncated_song).is_err());
+
+        for x2 in [f64::NAN, f64::INFINITY, 1e12] {
+            let mut far_line = vec![b'T', b'R', b'K', 0xF2, 1];
+            trk_string(&mut far_line, "");
+            trk_vector(&mut far_line, 0.0, 0.0);
+            far_line.extend(1i32.to_le_bytes());
+            far_line.push(1);
+            far_line.extend(0i32.to_le_bytes());
+            trk_vector(&mut far_line, 0.0, 0.0);
+            trk_vector(&mut far_line, x2, 0.0);
+            assert!(trk::read(&far_line).is_err(), "{x2}");
+        }
+    }
+
+    fn trk_with_metadata(entries: &[&str]) -> Vec<u8> {
+        let mut bytes = vec![b'T', b'R', b'K', 0xF2, 1];
+        trk_string(&mut bytes, "");
+        trk_vector(&mut bytes, 0.0, 0.0);
+        bytes.extend(0i32.to_le_bytes());
+        bytes.extend(b"META");
+        bytes.extend((entries.len() as i16).to_le_bytes());
+        for entry in entries {
+            trk_string(&mut bytes, entry);
+        }
+        bytes
+    }
+
+    #[test]
+    fn trk_physics_metadata() {
+        let file = trk::read(&trk_with_metadata(&[
+            "XGRAVITY=1",
+            "YGRAVITY=-0.5",
+            "GRAVITYWELLSIZE=5",
+        ]))
+        .expect("Failed to parse trk");
+        assert_eq!(file.track.gravity(), Vector2D(0.175, -0.0875));
+        assert_eq!(file.track.gravity_well_height(), 5.0);
+
+        let file = trk::read(&trk_with_metadata(&["YGRAVITY=1"])).expect("Failed to parse trk");
+        assert_eq!(file.track.gravity(), TrackMeta::default().gravity);
+
+        for entry in ["XGRAVITY=fast", "YGRAVITY=NaN", "GRAVITYWELLSIZE=0"] {
+            assert!(trk::read(&trk_with_metadata(&[entry])).is_err(), "{entry}");
+        }
+    }
+
+    #[test]
+    fn trk_round_trip() {
+        for fixture in [
+            "./fixtures/crash.track.json",
+            "./fixtures/cycloid.track.json",
+            "./fixtures/legacyTestTrack.track.json",
+            "./fixtures/testTrack.track.json",
+        ] {
+            let track = json::read(&fs::read_to_string(fixture).expect("Failed to read file"))
+                .expect("Failed to parse file");
+            let written = trk::write(&track).expect("Failed to write track");
+            let reread = trk::read(&written).expect("Failed to parse written track");
+
+            let features = trk::TrkFeatures {
+                six_one: track.physics_version() == PhysicsVersion::SixOne,
+                ..Default::default()
+            };
+            assert_eq!(reread.features, features, "{fixture}");
+            assert_eq!(reread.track.physics_version(), track.physics_version());
+            assert_eq!(track.all_lines(), reread.track.all_lines(), "{fixture}");
+            assert_eq!(
+                track.entity_positions_at(200),
+                reread.track.entity_positions_at(200),
+                "{fixture}"
+            );
+        }
+    }
+
+    #[test]
+    fn trk_round_trip_physics_metadata() {
+        let mut track = Track::new(
+            vec![Entity::starting_boshsled(
+                Vector2D(0.0, 0.0),
+                DEFAULT_START_VELOCITY,
+                0.0,
+            )],
+            vec![Line::builder().point(-20.0, 30.0).point(80.0, 60.0).build()],
+        );
+        track.set_gravity(Vector2D(0.1, 0.3));
+        track
+            .set_gravity_well_height(5.0)
+            .expect("gravity well height should be valid");
+
+        let written = trk::write(&track).expect("Failed to write track");
+        let reread = trk::read(&written).expect("Failed to parse written track");
+        assert_eq!(reread.track.gravity(), Vector2D(0.1, 0.3));
+        assert_eq!(reread.track.gravity_well_height(), 5.0);
+        assert_eq!(reread.metadata.len(), 3);
+        assert_eq!(
+            track.entity_positions_at(100),
+            reread.track.entity_positions_at(100)
+        );
+
+        // defaults are left out of the META block
+        let mut default_gravity = track.clone();
+        default_gravity.set_gravity(TrackMeta::default().gravity);
+        let reread = trk::read(&trk::write(&default_gravity).expect("Failed to write track"))
+            .expect("Failed to parse written track");
+        assert_eq!(
+            reread.metadata,
+            vec![("GRAVITYWELLSIZE".to_string(), "5".to_string())]
+        );
+    }
+
+    #[test]
+    fn trk_scenery_width() {
+        let bytes = fs::read("./fixtures/scenery.trk").expect("Failed to read file");
+        let file = trk::read(&bytes).expect("Failed to parse trk");
+        assert!(file.features.scenery_width);
+
+        let lines = file.track.all_lines();
+        let types: Vec<_> = lines.iter().map(|line| line.line_type).collect();
+        assert_eq!(
+            types,
+            vec![
+                LineType::Normal,
+                LineType::Scenery { width: 2.5 },
+                LineType::Scenery { width: 1.0 },
+                LineType::Scenery { width: 0.5 },
+            ]
+        );
+        assert_eq!(lines[1].ends.1.location, Vector2D(50.0, -10.0));
+
+        let written = trk::write(&file.track).expect("Failed to write track");
+        assert_eq!(written, bytes);
+
+        let json = json::write(&file.track).expect("Failed to write track");
+        let reread = json::read(&json).expect("Failed to parse written track");
+        assert_eq!(reread.all_lines(), lines);
+
+        let mut too_precise = file.track.clone();
+        too_precise
+            .add_line(
+                Line::builder()
+                    .id(-10)
+                    .line_type(LineType::Scenery { width: 0.25 })
+                    .point(0.0, 0.0)
+                    .point(1.0, 1.0)
+                    .build(),
+            )
+            .unwrap();
+        assert!(trk::write(&too_precise).is_err());
+    }
+
+    #[test]
+    fn trk_write_features() {
+        let mut rider = Entity::starting_boshsled(Vector2D(3.0, 4.0), DEFAULT_START_VELOCITY, 0.0);
+        rider.mutate_points(|p| {
+            p.previous_location = p.location;
+            p.momentum = Vector2D(0.0, 0.0);
+        });
+        rider.options.remountable = true;
+        let track = Track::new(
+            vec![rider],
+            vec![Line::builder()
+                .id(1)
+                .line_type(LineType::Accelerate { amount: 4.0 })
+                .point(0.0, 0.0)
+                .point(10.0, 0.0)
+                .build()],
+        );
+
+        let mut track = track;
+        track.set_frictionless(true);
+        track.info.song = Some(Song {
+            name: "ünïcode song.mp3".to_string(),
+            offset: 12.25,
+        });
+
+        let reread = trk::read(&trk::write(&track).expect("Failed to write track"))
+            .expect("Failed to parse written track");
+        assert_eq!(reread.track.info.song, track.info.song);
+        assert_eq!(
+            reread.features,
+            trk::TrkFeatures {
+                song_info: true,
+                red_multiplier: true,
+                zero_start: true,
+                remount: true,
+                frictionless: true,
+                ..Default::default()
+            }
+        );
+        assert!(reread.track.frictionless());
+        assert_eq!(track.all_lines(), reread.track.all_lines());
+        assert_eq!(
+            track.entity_positions_at(0),
+            reread.track.entity_positions_at(0)
+        );
+    }
+
+    #[test]
+    fn trk_write_errors() {
+        let two_riders = Track::new(
+            vec![Entity::starting_boshsled(Vector2D(0.0, 0.0), DEFAULT_START_VELOCITY, 0.0); 2],
+            vec![],
+        );
+        assert!(trk::write(&two_riders).is_err());
+
+        let big_multiplier = Track::new(
+            vec![Entity::starting_boshsled(
+                Vector2D(0.0, 0.0),
+                DEFAULT_START_VELOCITY,
+                0.0,
+            )],
+            vec![Line::builder()
+                .line_type(LineType::Accelerate { amount: 300.0 })
+                .point(0.0, 0.0)
+                .point(10.0, 0.0)
+                .build()],
+        );
+        assert!(trk::write(&big_multiplier).is_err());
+
+        let fractional_multiplier = Track::new(
+            vec![Entity::starting_boshsled(
+                Vector2D(0.0, 0.0),
+                DEFAULT_START_VELOCITY,
+                0.0,
+            )],
+            vec![Line::builder()
+                .line_type(LineType::Accelerate { amount: 0.5 })
+                .point(0.0, 0.0)
+                .point(10.0, 0.0)
+                .build()],
+        );
+        assert!(trk::write(&fractional_multiplier).is_err());
+
+        let custom_meta = Track::new_with_meta(
+            vec![Entity::starting_boshsled(
+                Vector2D(0.0, 0.0),
+                DEFAULT_START_VELOCITY,
+                0.0,
+            )],
+            vec![],
+            TrackMeta {
+                cell_size: 20.0,
+                ..Default::default()
+            },
+        );
+        assert!(trk::write(&custom_meta).is_err());
+
+        let mut custom_gravity = Track::new(
+            vec![Entity::starting_boshsled(
+                Vector2D(0.0, 0.0),
+                DEFAULT_START_VELOCITY,
+                0.0,
+            )],
+            vec![],
+        );
+        // 0.007 is not a multiple of Line Rider's gravity that reads back the same
+        custom_gravity.set_gravity(Vector2D(0.0, 0.007));
+        assert!(trk::write(&custom_gravity).is_err());
+
+        let mut invincible =
+            Entity::starting_boshsled(Vector2D(0.0, 0.0), DEFAULT_START_VELOCITY, 0.0);
+        invincible.options.invincible = true;
+        assert!(trk::write(&Track::new(vec![invincible], vec![])).is_err());
+    }
+
+    fn amf_name(bytes: &mut Vec<u8>, name: &str) {
+        bytes.extend((name.len() as u16).to_be_bytes());
+        bytes.extend(name.as_bytes());
+    }
+
+    fn amf_number(bytes: &mut Vec<u8>, n: f64) {
+        bytes.push(0x00);
+        bytes.extend(n.to_be_bytes());
+    }
+
+    fn amf_string(bytes: &mut Vec<u8>, s: &str) {
+        bytes.push(0x02);
+        amf_name(bytes, s);
+    }
+
+    fn amf_object_end(bytes: &mut Vec<u8>) {
+        bytes.extend([0x00, 0x00, 0x09]);
+    }
+
+    /// Encodes a flash line as an ECMA array, the way Line Rider saves them.
+    fn amf_line(bytes: &mut Vec<u8>, line: [f64; 10]) {
+        bytes.push(0x08);
+        bytes.extend(10u32.to_be_bytes());
+        for (i, value) in line.iter().enumerate() {
+            amf_name(bytes, &i.to_string());
+            match i {
+                5 => bytes.extend([0x01, *value as u8]),
+                6 | 7 => bytes.push(0x06),
+                _ => amf_number(bytes, *value),
+            }
+        }
+        amf_object_end(bytes);
+    }
+
+    /// A flash save's label, version tag, start position and lines. A start with a single
+    /// number is the index of the line to start on.
+    type SolTestTrack<'a> = (&'a str, Option<&'a str>, &'a [f64], Vec<[f64; 10]>);
+
+    fn sol_file(tracks: &[SolTestTrack]) -> Vec<u8> {
+        let mut body = vec![];
+        amf_name(&mut body, "savedLines");
+        body.extend(0u32.to_be_bytes());
+
+        amf_name(&mut body, "trackList");
+        body.push(0x0A);
+        body.extend((tracks.len() as u32).to_be_bytes());
+        for (label, version, start, lines) in tracks {
+            body.push(0x03);
+            amf_name(&mut body, "label");
+            amf_string(&mut body, label);
+            if let Some(version) = version {
+                amf_name(&mut body, "version");
+                amf_string(&mut body, version);
+            }
+            amf_name(&mut body, "startLine");
+            if let [index] = start {
+                amf_number(&mut body, *index);
+            } else {
+                body.push(0x0A);
+                body.extend((start.len() as u32).to_be_bytes());
+                for n in *start {
+                    amf_number(&mut body, *n);
+                }
+            }
+            amf_name(&mut body, "data");
+            body.push(0x0A);
+            body.extend((lines.len() as u32).to_be_bytes());
+            for line in lines {
+                amf_line(&mut body, *line);
+            }
+            amf_object_end(&mut body);
+        }
+        body.push(0x00);
+
+        let mut bytes = vec![0x00, 0xBF];
+        bytes.extend((body.len() as u32 + 10).to_be_bytes());
+        bytes.extend(b"TCSO");
+        bytes.extend([0x00, 0x04, 0x00, 0x00, 0x00, 0x00]);
+        bytes.extend(body);
+        bytes
+    }
+
+    #[test]
+    fn sol_lists_tracks() {
+        let bytes = sol_file(&[
+            ("old", None, &[0.0, 0.0], vec![]),
+            (
+                "flat",
+                Some("6.1"),
+                &[5.0, -10.0],
+                vec![
+                    [0.0, 5.0, 30.0, 5.0, 3.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+                    [30.0, 5.0, 60.0, 5.0, 0.0, 1.0, 0.0, 0.0, 2.0, 1.0],
+                ],
+            ),
+            ("newest", Some("6.2"), &[0.0, 0.0], vec![]),
+        ]);
+
+        let file = sol::read(&bytes).expect("Failed to parse sol");
+        let tracks: Vec<_> = file
+            .tracks
+            .iter()
+            .map(|t| (t.label.as_str(), t.physics_version().unwrap()))
+            .collect();
+        assert_eq!(
+            tracks,
+            vec![
+                ("old", PhysicsVersion::SixZero),
+                ("flat", PhysicsVersion::SixOne),
+                ("newest", PhysicsVersion::SixTwo),
+            ]
+        );
+
+        let track = file.tracks[1].to_track().expect("Failed to convert track");
+        assert_eq!(track.physics_version(), PhysicsVersion::SixOne);
+        let lines = track.all_lines();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].id, 1);
+        assert!(lines[0].ends.0.extended && lines[0].ends.1.extended);
+        assert_eq!(lines[1].line_type, LineType::Accelerate { amount: 1.0 });
+        assert!(lines[1].flipped);
+
+        let entities = track.entity_positions_at(0);
+        assert_eq!(
+            entities
+                .first()
+                .expect("rider should exist")
+                .point_at(PointIndex::SledPeg)
+                .location,
+            Vector2D(5.0, -10.0)
+        );
+    }
+
+    #[test]
+    fn sol_errors() {
+        assert!(sol::read(b"not a sol").is_err());
+
+        let bytes = sol_file(&[(
+            "bad",
+            Some("6.2"),
+            &[0.0, 0.0],
+            vec![[0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 5.0]],
+        )]);
+        let file = sol::read(&bytes).expect("Failed to parse sol");
+        assert!(file.tracks[0].to_track().is_err());
+
+        assert!(sol::read(&bytes[..bytes.len() - 5]).is_err());
+
+        // an unknown version only fails its own track
+        let bytes = sol_file(&[
+            ("future", Some("7.0"), &[0.0, 0.0], vec![]),
+            ("newest", Some("6.2"), &[0.0, 0.0], vec![]),
+        ]);
+        let file = sol::read(&bytes).expect("Failed to parse sol");
+        assert_eq!(file.tracks[0].version_tag.as_deref(), Some("7.0"));
+        assert!(file.tracks[0].physics_version().is_err());
+        assert!(file.tracks[0].to_track().is_err());
+        assert!(file.tracks[1].to_track().is_ok());
+
+        for x2 in [f64::NAN, f64::INFINITY, 1e12] {
+            let bytes = sol_file(&[(
+                "far",
+                Some("6.2"),
+                &[0.0, 0.0],
+                vec![[0.0, 0.0, x2, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0]],
+            )]);
+            let file = sol::read(&bytes).expect("Failed to parse sol");
+            assert!(file.tracks[0].to_track().is_err(), "{x2}");
+        }
+    }
+
+    #[test]
+    fn sol_start_line() {
+        let lines = vec![
+            [0.0, 5.0, 30.0, 5.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+            [40.0, 8.0, 60.0, 8.0, 0.0, 0.0, 0.0, 0.0, 2.0, 0.0],
+        ];
+        let start_of = |index: f64| {
+            let bytes = sol_file(&[("start", Some("6.2"), &[index], lines.clone())]);
+            let file = sol::read(&bytes).expect("Failed to parse sol");
+            file.tracks[0].to_track().map(|track| {
+                track.entity_positions_at(0)[0]
+                    .point_at(PointIndex::SledPeg)
+                    .location
+            })
+        };
+
+        assert_eq!(start_of(1.0).unwrap(), Vector2D(40.0, 8.0));
+        for index in [-1.0, 0.5, f64::NAN, 2.0] {
+            assert!(start_of(index).is_err(), "{index}");
+        }
+    }
+
+    #[test]
+    fn amf0_malformed_values() {
+        use super::amf0::Amf0Value;
+        use std::io::Cursor;
+
+        // a long string claiming 4 GiB, followed by a few bytes
+        let long_string = [0x0c, 0xff, 0xff, 0xff, 0xff, b'a', b'b'];
+        assert!(Amf0Value::read_from(&mut Cursor::new(&long_string[..])).is_err());
+
+        // strict arrays of one element nested far deeper than any track
+        let nested: Vec<u8> = std::iter::repeat_n([0x0a, 0, 0, 0, 1], 100_000)
+            .flatten()
+            .chain([0x05])
+            .collect();
+        assert!(Amf0Value::read_from(&mut Cursor::new(&nested[..])).is_err());
+
+        let shallow: Vec<u8> = std::iter::repeat_n([0x0a, 0, 0, 0, 1], 8)
+            .flatten()
+            .chain([0x05])
+            .collect();
+        assert!(Amf0Value::read_from(&mut Cursor::new(&shallow[..])).is_ok());
+    }
+
+    #[test]
+    fn json_adjacency() {
+        let track = json::read(
+            &fs::read_to_string("./fixtures/testTrack.track.json").expect("Failed to read file"),
+        )
+        .expect("Failed to parse file");
+
+        let line = track.line_with_id(2209).expect("line 2209 should exist");
+        assert_eq!(line.left_line, None);
+        assert_eq!(track.right_line_of(line).map(|l| l.id), Some(2210));
+        let next = track.right_line_of(line).expect("line 2210 should exist");
+        assert_eq!(track.left_line_of(next).map(|l| l.id), Some(2209));
+
+        // the neighbor of this line was deleted before the track was saved
+        let dangling = track.line_with_id(2487).expect("line 2487 should exist");
+        assert_eq!(dangling.left_line, Some(2486));
+        assert!(track.left_line_of(dangling).is_none());
+
+        let reread = json::read(&json::write(&track).expect("Failed to write track"))
+            .expect("Failed to parse written track");
+        let adjacency = |track: &Track| -> Vec<_> {
+            track
+                .all_lines()
+                .iter()
+                .map(|l| (l.id, l.left_line, l.right_line))
+                .collect()
+        };
+        assert_eq!(adjacency(&track), adjacency(&reread));
+    }
+
+    #[test]
+    fn json_info() {
+        let track = json::read(
+            &fs::read_to_string("./fixtures/crash.track.json").expect("Failed to read file"),
+        )
+        .expect("Failed to parse file");
+
+        let info = TrackInfo {
+            title: "cripple".to_string(),
+            creator: "".to_string(),
+            description: "".to_string(),
+            duration: Some(40),
+            version: Some("6.2".to_string()),
+            song: None,
+        };
+        assert_eq!(track.info, info);
+
+        let reread = json::read(&json::write(&track).expect("Failed to write track"))
+            .expect("Failed to parse written track");
+        assert_eq!(reread.info, info);
+    }
+
+    #[test]
+    fn recording_round_trip() {
+        let track = json::read(
+            &fs::read_to_string("./fixtures/crash.track.json").expect("Failed to read file"),
+        )
+        .expect("Failed to parse file");
+        let frames: Vec<_> = (0..600).map(|i| track.entity_positions_at(i)).collect();
+        assert!(frames.iter().any(|f| f.len() > 1), "rider should crash");
+
+        let fingerprint = recording::track_fingerprint(&track);
+        let bytes = recording::write(fingerprint, &frames, false).expect("Failed to write");
+        let decoded = recording::read(&bytes).expect("Failed to read");
+
+        assert_eq!(decoded.fingerprint, fingerprint);
+        assert!(!decoded.quantized);
+        assert_eq!(decoded.frames, frames);
+
+        let json_size = serde_json::to_string(&frames)
+            .expect("Failed to serialize frames")
+            .len();
+        assert!(
+            bytes.len() * 10 < json_size,
+            "{} vs {}",
+            bytes.len(),
+            json_size
+        );
+    }
+
+    #[test]
+    fn recording_mount_state() {
+        let mut rider = Entity::starting_boshsled(Vector2D(0.0, 0.0), DEFAULT_START_VELOCITY, 0.0);
+        rider.options.remountable = true;
+        let track = Track::new(
+            vec![rider],
+            vec![
+                Line::builder()
+                    .point(-20.0, 20.0)
+                    .point(200.0, 120.0)
+                    .build(),
+                Line::builder()
+                    .point(200.0, 120.0)
+                    .point(220.0, 100.0)
+                    .build(),
+                Line::builder()
+                    .point(220.0, 100.0)
+                    .point(800.0, 130.0)
+                    .build(),
+            ],
+        );
+        let frames: Vec<_> = (0..200).map(|i| track.entity_positions_at(i)).collect();
+        assert!(
+            frames
+                .iter()
+                .any(|f| f[0].mount_state != MountState::Mounted),
+            "rider should dismount"
+        );
+
+        let bytes = recording::write(0, &frames, false).expect("Failed to write");
+        let decoded = recording::read(&bytes).expect("Failed to read");
+        assert_eq!(decoded.frames, frames);
+    }
+
+    #[test]
+    fn recording_quantized() {
+        let track = json::read(
+            &fs::read_to_string("./fixtures/testTrack.track.json").expect("Failed to read file"),
+        )
+        .expect("Failed to parse file");
+        let frames: Vec<_> = (0..300).map(|i| track.entity_positions_at(i)).collect();
+
+        let fingerprint = recording::track_fingerprint(&track);
+        let lossless = recording::write(fingerprint, &frames, false).expect("Failed to write");
+        let quantized = recording::write(fingerprint, &frames, true).expect("Failed to write");
+        assert!(quantized.len() < lossless.len());
+
+        let decoded = recording::read(&quantized).expect("Failed to read");
+        assert!(decoded.quantized);
+        assert_eq!(decoded.frames.len(), frames.len());
+        for (decoded, frame) in decoded.frames.iter().zip(&frames) {
+            for (decoded, entity) in decoded.iter().zip(frame) {
+                assert_eq!(decoded.bones, entity.bones);
+                for (index, point) in &entity.points {
+                    let error = decoded
+                        .point_at(*index)
+                        .location
+                        .distance_squared(point.location)
+                        .sqrt();
+                    assert!(error < 1e-3, "{error}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn recording_fingerprint() {
+        let track = Track::new(
+            vec![Entity::starting_boshsled(
+                Vector2D(0.0, 0.0),
+                DEFAULT_START_VELOCITY,
+                0.0,
+            )],
+            vec![],
+        );
+        let mut moved = track.clone();
+        moved
+            .add_line(Line::builder().point(0.0, 5.0).point(30.0, 5.0).build())
+            .unwrap();
+
+        assert_eq!(
+            recording::track_fingerprint(&track),
+            recording::track_fingerprint(&track.clone())
+        );
+        assert_ne!(
+            recording::track_fingerprint(&track),
+            recording::track_fingerprint(&moved)
+        );
+
+        let accel_track = |amount| {
+            Track::new(
+                vec![],
+                vec![Line::builder()
+                    .point(0.0, 5.0)
+                    .point(30.0, 5.0)
+                    .line_type(LineType::Accelerate { amount })
+                    .build()],
+            )
+        };
+        assert_ne!(
+            recording::track_fingerprint(&accel_track(1.0)),
+            recording::track_fingerprint(&accel_track(1.5))
+        );
+
+        let bytes = recording::write(0, &[], false).expect("Failed to write");
+        assert!(recording::read(&bytes[..bytes.len() - 1]).is_err());
+        assert!(recording::read(b"BREC").is_err());
+
+        // a layout whose bones or joints refer to points that the entity does not have
+        let mut dangling_bone = Entity::default_bosh();
+        let bone = Bone {
+            p2: PointIndex::SledPeg,
+            ..dangling_bone.bones[0]
+        };
+        dangling_bone.bones.push(bone);
+        let mut dangling_joint = Entity::default_sled();
+        dangling_joint.joints = Entity::default_boshsled().joints;
+        for entity in [dangling_bone, dangling_joint] {
+            let bytes = recording::write(0, &[vec![entity]], false).expect("Failed to write");
+            assert!(recording::read(&bytes).is_err());
+        }
+    }
+
+    #[test]
+    fn svg_line_styles() {
+        let mut track = Track::new(vec![], vec![]);
+        track
+            .add_line(Line::builder().point(0.0, 0.0).point(10.0, 0.0).build())
+            .unwrap();
+        track
+            .add_line(
+                Line::builder()
+                    .point(0.0, 10.0)
+                    .point(10.0, 10.0)
+                    .flipped(true)
+                    .build(),
+            )
+            .unwrap();
+        track
+            .add_line(
+                Line::builder()
+                    .point(0.0, 20.0)
+                    .point(10.0, 20.0)
+                    .line_type(LineType::Accelerate { amount: 1.0 })
+                    .build(),
+            )
+            .unwrap();
+        track
+            .add_line(
+                Line::builder()
+                    .point(0.0, 30.0)
+                    .point(10.0, 30.0)
+                    .line_type(LineType::Scenery { width: 1.0 })
+                    .build(),
+            )
+            .unwrap();
+
+        let image = svg::write(&track, &svg::SvgOptions::default());
+        assert!(image.starts_with("<svg"));
+        assert!(image.ends_with("</svg>"));
+        assert!(image.contains(r#"viewBox="-10 -10 30 50""#));
+        assert_eq!(image.matches(r#"<line class="normal""#).count(), 2);
+        assert_eq!(image.matches(r#"<line class="accelerate""#).count(), 1);
+        assert_eq!(image.matches(r#"<line class="scenery""#).count(), 1);
+        assert_eq!(image.matches(r#"<line class="side"#).count(), 3);
+        assert_eq!(image.matches(" flipped").count(), 1);
+        assert!(!image.contains("<polyline"));
+
+        // the markers of a flipped and an unflipped line point to opposite sides
+        let side_offsets: Vec<f64> = image
+            .split(r#"<line class="side"#)
+            .skip(1)
+            .map(|marker| {
+                let y = |attr: &str| -> f64 {
+                    let start = marker.find(attr).unwrap() + attr.len();
+                    let end = start + marker[start..].find('"').unwrap();
+                    marker[start..end].parse().unwrap()
+                };
+                y(r#"y2=""#) - y(r#"y1=""#)
+            })
+            .collect();
+        assert!(side_offsets[0] * side_offsets[1] < 0.0);
+    }
+
+    #[test]
+    fn svg_trajectories() {
+        let mut track = Track::new(
+            vec![Entity::starting_boshsled(
+                Vector2D(0.0, 0.0),
+                DEFAULT_START_VELOCITY,
+                0.0,
+            )],
+            vec![],
+        );
+        track
+            .add_line(
+                Line::builder()
+                    .point(-20.0, 20.0)
+                    .point(200.0, 20.0)
+                    .build(),
+            )
+            .unwrap();
+
+        let image = svg::write(
+            &track,
+            &svg::SvgOptions {
+                points: vec![PointIndex::SledPeg, PointIndex::BoshShoulder],
+                frames: 0..40,
+            },
+        );
+        assert_eq!(image.matches("<polyline").count(), 2);
+        assert!(image.contains(r#"data-point="SledPeg""#));
+        assert!(image.contains(r#"data-point="BoshShoulder""#));
+
+        let frames: Vec<_> = (0..40).map(|f| track.entity_positions_at(f)).collect();
+        let peg = frames[39][0].points[&PointIndex::SledPeg].location;
+        assert!(image.contains(&format!("{},{}", peg.0, peg.1)));
+
+        let split = vec![
+            vec![
+                Entity::starting_boshsled(Vector2D(0.0, 0.0), DEFAULT_START_VELOCITY, 0.0),
+                Entity::starting_boshsled(Vector2D(50.0, 0.0), DEFAULT_START_VELOCITY, 0.0)
+            ];
+            3
+        ];
+        let image = svg::render(track.all_lines(), &split, &[PointIndex::SledPeg]);
+        assert_eq!(image.matches("<polyline").count(), 2);
+    }
+}
@@ -0,0 +1,211 @@
+use std::io::Cursor;
+
+use anyhow::{bail, Context, Result};
+use read_from::ReadFrom;
+
+use crate::formats::amf0::{read_be, read_string, Amf0Value};
+use crate::game::{
+    Line, LineType, PhysicsVersion, Track, TrackMeta, Vector2D, DEFAULT_SCENERY_WIDTH,
+};
+use crate::rider::DEFAULT_START_VELOCITY;
+
+const MAGIC: [u8; 2] = [0x00, 0xBF];
+const SIGNATURE: [u8; 10] = [b'T', b'C', b'S', b'O', 0x00, 0x04, 0x00, 0x00, 0x00, 0x00];
+const AMF0_VERSION: u32 = 0;
+
+/// The root property which holds the saved tracks.
+const TRACK_LIST: &str = "trackList";
+
+/// A track saved in a `.sol` file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SolTrack {
+    pub label: String,
+    /// The version of Line Rider that saved the track, which tracks saved before version
+    /// tags existed do not have.
+    pub version_tag: Option<String>,
+
+    data: Amf0Value,
+}
+
+/// A Flash shared object holding Line Rider saves.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SolFile {
+    pub tracks: Vec<SolTrack>,
+}
+
+/// Reads a Flash `.sol` shared object containing Line Rider tracks.
+pub fn read(bytes: &[u8]) -> Result<SolFile> {
+    let mut input = Cursor::new(bytes);
+
+    let magic: [u8; 2] = ReadFrom::read_from(&mut input).context("error while reading magic")?;
+    if magic != MAGIC {
+        bail!("not a .sol file");
+    }
+    let _length: u32 = read_be(&mut input, "file length")?;
+    let signature: [u8; 10] =
+        ReadFrom::read_from(&mut input).context("error while reading signature")?;
+    if signature != SIGNATURE {
+        bail!("not a .sol file");
+    }
+    let _name = read_string(&mut input)?;
+    let amf_version: u32 = read_be(&mut input, "amf version")?;
+    if amf_version != AMF0_VERSION {
+        bail!("unsupported amf version {}", amf_version);
+    }
+
+    let mut track_list = None;
+    while (input.position() as usize) < bytes.len() {
+        let name = read_string(&mut input)?;
+        let value = Amf0Value::read_from(&mut input)
+            .with_context(|| format!("error while reading {name:?}"))?;
+        let _padding = u8::read_from(&mut input).context("error while reading padding")?;
+
+        if name == TRACK_LIST {
+            track_list = Some(value);
+        }
+    }
+
+    let track_list = track_list.context(".sol file has no track list")?;
+    let tracks = track_list
+        .elements()
+        .into_iter()
+        .map(SolTrack::from_amf0)
+        .collect();
+
+    Ok(SolFile { tracks })
+}
+
+impl SolTrack {
+    fn from_amf0(value: &Amf0Value) -> SolTrack {
+        let label = value
+            .get("label")
+            .and_then(Amf0Value::as_str)
+            .unwrap_or_default()
+            .to_string();
+        let version_tag = value
+            .get("version")
+            .and_then(Amf0Value::as_str)
+            .map(str::to_string);
+
+        SolTrack {
+            label,
+            version_tag,
+            data: value.clone(),
+        }
+    }
+
+    /// The physics the track rides with. Tracks without a version tag are from 6.0, and an
+    /// error is returned for tags of unknown versions.
+    pub fn physics_version(&self) -> Result<PhysicsVersion> {
+        match self.version_tag.as_deref() {
+            None => Ok(PhysicsVersion::SixZero),
+            Some(tag) => PhysicsVersion::from_tag(tag)
+                .with_context(|| format!("track {:?} has unknown version {:?}", self.label, tag)),
+        }
+    }
+
+    /// Converts this save into a [`Track`].
+    pub fn to_track(&self) -> Result<Track> {
+        let physics_version = self.physics_version()?;
+        let line_data = self
+            .data
+            .get("data")
+            .with_context(|| format!("track {:?} has no line data", self.label))?
+            .elements();
+
+        let meta = TrackMeta {
+            physics_version,
+            ..Default::default()
+        };
+
+        let mut track = Track::new_with_meta(vec![], vec![], meta);
+        track.info.title = self.label.clone();
+        track.info.version = Some(physics_version.tag().to_string());
+        for (i, line_data) in line_data.iter().enumerate() {
+            let line = read_line(line_data, &track, i)
+                .with_context(|| format!("error while reading line {i}"))?;
+            track.add_line(line)?;
+        }
+
+        let start = self.start_position(&line_data)?;
+        track.create_rider(start, DEFAULT_START_VELOCITY, 0.0);
+
+        Ok(track)
+    }
+
+    /// `startLine` is either an `[x, y]` array or the index of a line to start on.
+    fn start_position(&self, line_data: &[&Amf0Value]) -> Result<Vector2D> {
+        let start = match self.data.get("startLine") {
+            None => return Ok(Vector2D(0.0, 0.0)),
+            Some(start) => start,
+        };
+
+        if let Some(index) = start.as_f64() {
+            // `as usize` would read negative and NaN indices as the first line
+            if !(index >= 0.0 && index.fract() == 0.0) {
+                bail!("start line {index} is not a valid index");
+            }
+            let line = line_data
+                .get(index as usize)
+                .with_context(|| format!("start line {index} does not exist"))?;
+            Ok(Vector2D(number_at(line, 0)?, number_at(line, 1)?))
+        } else {
+            Ok(Vector2D(number_at(start, 0)?, number_at(start, 1)?))
+        }
+    }
+}
+
+/// Lines are arrays of `[x1, y1, x2, y2, extended, flipped, leftLine, rightLine, id, type]`.
+fn read_line(line: &Amf0Value, track: &Track, index: usize) -> Result<Line> {
+    let line_type = match number_at(line, 9)? as i64 {
+        0 => LineType::Normal,
+        1 => LineType::Accelerate { amount: 1.0 },
+        2 => LineType::Scenery {
+            width: DEFAULT_SCENERY_WIDTH,
+        },
+        other => bail!("unknown line type {}", other),
+    };
+
+    let id = match line.get("8") {
+        Some(id) if !id.is_missing() => id.as_f64().context("line id is not a number")? as i64,
+        _ => index as i64,
+    };
+
+    let extension = match line.get("4") {
+        Some(extension) if !extension.is_missing() => {
+            extension.as_f64().context("extension is not a number")? as u8
+        }
+        _ => 0,
+    };
+
+    let flipped = line.get("5").and_then(Amf0Value::as_bool).unwrap_or(false);
+
+    let left_line = line
+        .get("6")
+        .and_then(Amf0Value::as_f64)
+        .map(|id| id as i64);
+    let right_line = line
+        .get("7")
+        .and_then(Amf0Value::as_f64)
+        .map(|id| id as i64);
+
+    Ok(track
+        .line_builder()
+        .id(id)
+        .line_type(line_type)
+        .flipped(flipped)
+        .left_line(left_line)
+        .right_line(right_line)
+        .point(number_at(line, 0)?, number_at(line, 1)?)
+        .extended(extension & 1 != 0)
+        .point(number_at(line, 2)?, number_at(line, 3)?)
+        .extended(extension & 2 != 0)
+        .build())
+}
+
+fn number_at(value: &Amf0Value, index: usize) -> Result<f64> {
+    value
+        .get(&index.to_string())
+        .and_then(Amf0Value::as_f64)
+        .with_context(|| format!("expected a number at index {index}"))
+}
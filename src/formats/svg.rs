@@ -0,0 +1,175 @@
+use std::fmt::Write;
+use std::ops::Range;
+
+use crate::game::{Line, LineType, Track, Vector2D};
+use crate::rider::{Entity, PointIndex};
+
+/// Space around the drawing, in track units.
+const PADDING: f64 = 10.0;
+/// Length of the marker showing which side of a line is solid.
+const SIDE_MARKER_LENGTH: f64 = 3.0;
+
+const STYLE: &str = "line{stroke-linecap:round}\
+.normal{stroke:#0066ff;stroke-width:2}\
+.accelerate{stroke:#dd4444;stroke-width:2}\
+.scenery{stroke:#00cc00;stroke-width:1}\
+.side{stroke:#0066ff;stroke-width:1}\
+.accelerate-side{stroke:#dd4444}\
+.flipped{stroke-dasharray:1 1}\
+.trajectory{fill:none;stroke-width:0.5}";
+
+/// Colors used for trajectories, in order.
+const TRAJECTORY_COLORS: [&str; 6] = [
+    "#ff8800", "#8800ff", "#00aaaa", "#aa0066", "#666666", "#88aa00",
+];
+
+/// Options for [`write`].
+#[derive(Clone, Debug, Default)]
+pub struct SvgOptions {
+    /// Points whose paths are drawn over the track.
+    pub points: Vec<PointIndex>,
+    /// Frames that the paths cover.
+    pub frames: Range<usize>,
+}
+
+/// Renders a track as an SVG image, optionally with the paths of some rider points.
+pub fn write(track: &Track, options: &SvgOptions) -> String {
+    let frames: Vec<Vec<Entity>> = if options.points.is_empty() {
+        vec![]
+    } else {
+        options
+            .frames
+            .clone()
+            .map(|frame| track.entity_positions_at(frame))
+            .collect()
+    };
+
+    render(track.all_lines(), &frames, &options.points)
+}
+
+/// Renders lines as an SVG image, along with the path that each of `points` takes
+/// over `frames`.
+///
+/// Normal, acceleration and scenery lines each have their own class. Physics lines get a
+/// short `side` marker on the side that riders collide with, which also has the `flipped`
+/// class if the line is flipped.
+pub fn render(lines: &[Line], frames: &[Vec<Entity>], points: &[PointIndex]) -> String {
+    let trajectories: Vec<(PointIndex, Vec<Vector2D>)> = points
+        .iter()
+        .flat_map(|index| {
+            trajectories_of(frames, *index)
+                .into_iter()
+                .map(|path| (*index, path))
+        })
+        .collect();
+
+    let bounds = lines
+        .iter()
+        .flat_map(|line| [line.ends.0.location, line.ends.1.location])
+        .chain(
+            trajectories
+                .iter()
+                .flat_map(|(_, path)| path.iter().copied()),
+        )
+        .fold(
+            None,
+            |bounds: Option<(Vector2D, Vector2D)>, p| match bounds {
+                None => Some((p, p)),
+                Some((min, max)) => Some((
+                    Vector2D(min.0.min(p.0), min.1.min(p.1)),
+                    Vector2D(max.0.max(p.0), max.1.max(p.1)),
+                )),
+            },
+        )
+        .unwrap_or_default();
+    let min = bounds.0 - Vector2D(PADDING, PADDING);
+    let size = bounds.1 - bounds.0 + Vector2D(PADDING * 2.0, PADDING * 2.0);
+
+    let mut svg = String::new();
+    // writing to a String cannot fail
+    let _ = write!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="{} {} {} {}">"#,
+        min.0, min.1, size.0, size.1
+    );
+    let _ = write!(svg, "<style>{STYLE}</style>");
+
+    svg.push_str(r#"<g class="lines">"#);
+    for line in lines {
+        let class = match line.line_type {
+            LineType::Normal => "normal",
+            LineType::Accelerate { .. } => "accelerate",
+            LineType::Scenery { .. } => "scenery",
+        };
+        write_line(&mut svg, class, line.ends.0.location, line.ends.1.location);
+    }
+    svg.push_str("</g>");
+
+    svg.push_str(r#"<g class="sides">"#);
+    for line in lines {
+        if matches!(line.line_type, LineType::Scenery { .. }) || line.length_squared() == 0.0 {
+            continue;
+        }
+
+        let mut class = String::from("side");
+        if matches!(line.line_type, LineType::Accelerate { .. }) {
+            class.push_str(" accelerate-side");
+        }
+        if line.flipped {
+            class.push_str(" flipped");
+        }
+
+        let middle = (line.ends.0.location + line.ends.1.location) / 2.0;
+        let marker_end = middle + line.perpendicular() * SIDE_MARKER_LENGTH;
+        write_line(&mut svg, &class, middle, marker_end);
+    }
+    svg.push_str("</g>");
+
+    svg.push_str(r#"<g class="trajectories">"#);
+    for (i, (index, path)) in trajectories.iter().enumerate() {
+        let color = TRAJECTORY_COLORS[i % TRAJECTORY_COLORS.len()];
+        let _ = write!(
+            svg,
+            r#"<polyline class="trajectory" data-point="{index:?}" stroke="{color}" points=""#
+        );
+        for (j, p) in path.iter().enumerate() {
+            if j > 0 {
+                svg.push(' ');
+            }
+            let _ = write!(svg, "{},{}", p.0, p.1);
+        }
+        svg.push_str(r#""/>"#);
+    }
+    svg.push_str("</g>");
+
+    svg.push_str("</svg>");
+    svg
+}
+
+fn write_line(svg: &mut String, class: &str, p1: Vector2D, p2: Vector2D) {
+    let _ = write!(
+        svg,
+        r#"<line class="{}" x1="{}" y1="{}" x2="{}" y2="{}"/>"#,
+        class, p1.0, p1.1, p2.0, p2.1
+    );
+}
+
+/// Returns one path per rider for a point. The nth occurrence of a point in a frame
+/// belongs to the nth path, which keeps paths separate when there are multiple riders.
+fn trajectories_of(frames: &[Vec<Entity>], index: PointIndex) -> Vec<Vec<Vector2D>> {
+    let mut paths: Vec<Vec<Vector2D>> = vec![];
+    for frame in frames {
+        let locations = frame
+            .iter()
+            .filter_map(|entity| entity.points.get(&index))
+            .map(|point| point.location);
+        for (n, location) in locations.enumerate() {
+            if paths.len() <= n {
+                paths.push(vec![]);
+            }
+            paths[n].push(location);
+        }
+    }
+
+    paths
+}
@@ -0,0 +1,601 @@
+use std::io::{self, Cursor, Read, Write};
+
+use anyhow::{bail, Context, Result};
+use read_from::{LittleEndian, ReadFrom, WriteTo};
+
+use crate::game::{
+    Line, LineType, PhysicsVersion, Song, Track, TrackMeta, Vector2D, DEFAULT_GRAVITY,
+    DEFAULT_SCENERY_WIDTH,
+};
+use crate::rider::{Entity, PointIndex, RiderOptions, DEFAULT_START_VELOCITY};
+
+/// `TRK` followed by `0xF2`.
+const MAGIC: [u8; 4] = [b'T', b'R', b'K', 0xF2];
+/// `META`, marks the optional metadata block after the lines.
+const META_MAGIC: [u8; 4] = [b'M', b'E', b'T', b'A'];
+const SUPPORTED_VERSION: u8 = 1;
+
+const FEATURE_RED_MULTIPLIER: &str = "REDMULTIPLIER";
+const FEATURE_SCENERY_WIDTH: &str = "SCENERYWIDTH";
+const FEATURE_SIX_ONE: &str = "6.1";
+const FEATURE_SONG_INFO: &str = "SONGINFO";
+const FEATURE_IGNORABLE_TRIGGER: &str = "IGNORABLE_TRIGGER";
+const FEATURE_ZERO_START: &str = "ZEROSTART";
+const FEATURE_REMOUNT: &str = "REMOUNT";
+const FEATURE_FRICTIONLESS: &str = "FRICTIONLESS";
+
+/// `META` entries that change physics. Gravity is stored as a multiple of Line Rider's
+/// gravity along each axis.
+const META_X_GRAVITY: &str = "XGRAVITY";
+const META_Y_GRAVITY: &str = "YGRAVITY";
+const META_GRAVITY_WELL_SIZE: &str = "GRAVITYWELLSIZE";
+
+const LINE_TYPE_SCENERY: u8 = 0;
+const LINE_TYPE_NORMAL: u8 = 1;
+const LINE_TYPE_ACCELERATE: u8 = 2;
+
+/// Scenery widths are stored in tenths.
+const SCENERY_WIDTH_SCALE: f64 = 10.0;
+
+/// The features a `.trk` file declared in its header.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TrkFeatures {
+    /// Acceleration lines store their own multiplier.
+    pub red_multiplier: bool,
+    /// Scenery lines store their own width.
+    pub scenery_width: bool,
+    /// The track should be simulated with 6.1 physics.
+    pub six_one: bool,
+    /// The file contains a song name and offset.
+    pub song_info: bool,
+    /// Physics lines may carry zoom triggers.
+    pub ignorable_trigger: bool,
+    /// The rider starts with no velocity.
+    pub zero_start: bool,
+    /// The rider may remount its sled.
+    pub remount: bool,
+    /// Friction is disabled.
+    pub frictionless: bool,
+}
+
+/// The result of reading a `.trk` file.
+#[derive(Clone, Debug)]
+pub struct TrkFile {
+    pub track: Track,
+    pub features: TrkFeatures,
+    /// `KEY=VALUE` entries from the trailing `META` block, in file order. Gravity and gravity
+    /// well entries are also applied to `track`.
+    pub metadata: Vec<(String, String)>,
+}
+
+/// Reads a Linerider Advanced `.trk` file.
+pub fn read(bytes: &[u8]) -> Result<TrkFile> {
+    let mut input = Cursor::new(bytes);
+
+    let magic: [u8; 4] = ReadFrom::read_from(&mut input).context("error while reading magic")?;
+    if magic != MAGIC {
+        bail!("not a .trk file");
+    }
+
+    let version = u8::read_from(&mut input).context("error while reading version")?;
+    if version != SUPPORTED_VERSION {
+        bail!("unsupported .trk version {}", version);
+    }
+
+    let features = read_features(&read_short_string(&mut input)?)?;
+
+    let song = if features.song_info {
+        Some(read_song(&read_dotnet_string(&mut input)?)?)
+    } else {
+        None
+    };
+
+    let start = Vector2D::read_from(&mut input).context("error while reading start position")?;
+
+    let line_count: i32 = read_le(&mut input, "line count")?;
+    if line_count < 0 {
+        bail!("negative line count {}", line_count);
+    }
+
+    let rider = start_rider(start, features.zero_start, features.remount);
+    let physics_version = if features.six_one {
+        PhysicsVersion::SixOne
+    } else {
+        PhysicsVersion::SixTwo
+    };
+    let meta = TrackMeta {
+        physics_version,
+        frictionless: features.frictionless,
+        ..Default::default()
+    };
+    let mut track = Track::new_with_meta(vec![rider], vec![], meta);
+    track.info.song = song;
+    track.info.version = Some(physics_version.tag().to_string());
+
+    let mut next_scenery_id = -1;
+    for _ in 0..line_count {
+        let line = read_line(&mut input, &track, &features, &mut next_scenery_id)?;
+        track.add_line(line)?;
+    }
+
+    let metadata = if (input.position() as usize) < bytes.len() {
+        read_metadata(&mut input)?
+    } else {
+        vec![]
+    };
+    apply_metadata(&mut track, &metadata)?;
+
+    Ok(TrkFile {
+        track,
+        features,
+        metadata,
+    })
+}
+
+/// Writes a [`Track`] as a Linerider Advanced `.trk` file.
+///
+/// Only the features that the track needs are declared, and gravity and gravity well size
+/// are written to a `META` block if they are not Line Rider's. Returns an error if the track
+/// contains something `.trk` cannot represent, such as multiple riders or a non-default
+/// cell size.
+pub fn write(track: &Track) -> Result<Vec<u8>> {
+    let meta = track.meta();
+    let default_meta = TrackMeta::default();
+    if meta.line_extension_ratio != default_meta.line_extension_ratio
+        || meta.cell_size != default_meta.cell_size
+        || meta.iterations != default_meta.iterations
+    {
+        bail!(".trk cannot represent non-default line extension, cell size or iterations");
+    }
+    let metadata = physics_metadata(track)?;
+    if track.physics_version() == PhysicsVersion::SixZero {
+        bail!(".trk cannot represent 6.0 physics");
+    }
+
+    let entities = track.entity_positions_at(0);
+    let (start, zero_start, remount) = match entities.as_slice() {
+        [rider] => rider_start(rider)?,
+        _ => bail!(".trk requires exactly one rider, found {}", entities.len()),
+    };
+
+    let mut features = TrkFeatures {
+        six_one: track.physics_version() == PhysicsVersion::SixOne,
+        song_info: track.info.song.is_some(),
+        zero_start,
+        remount,
+        frictionless: track.frictionless(),
+        ..Default::default()
+    };
+    for line in track.all_lines() {
+        match line.line_type {
+            LineType::Accelerate { amount } => {
+                if !(0.0..=u8::MAX as f64).contains(&amount) || amount.fract() != 0.0 {
+                    bail!(
+                        "line {} has multiplier {}, .trk only supports whole numbers up to {}",
+                        line.id,
+                        amount,
+                        u8::MAX
+                    );
+                }
+                features.red_multiplier |= amount != 1.0;
+            }
+            LineType::Normal => {}
+            LineType::Scenery { width } => {
+                let stored = (width * SCENERY_WIDTH_SCALE).round();
+                if !(0.0..=u8::MAX as f64).contains(&stored)
+                    || stored / SCENERY_WIDTH_SCALE != width
+                {
+                    bail!(
+                        "line {} has width {}, .trk only supports tenths up to {}",
+                        line.id,
+                        width,
+                        u8::MAX as f64 / SCENERY_WIDTH_SCALE
+                    );
+                }
+                features.scenery_width |= width != DEFAULT_SCENERY_WIDTH;
+                continue;
+            }
+        }
+        for id in [Some(line.id), line.left_line, line.right_line]
+            .into_iter()
+            .flatten()
+        {
+            if i32::try_from(id).is_err() {
+                bail!("line id {} does not fit in .trk", id);
+            }
+        }
+    }
+
+    let mut output = MAGIC.to_vec();
+    SUPPORTED_VERSION.write_to(&mut output)?;
+    write_short_string(&mut output, &write_features(&features))?;
+    if let Some(song) = &track.info.song {
+        write_dotnet_string(&mut output, &format!("{}\r\n{}", song.name, song.offset))?;
+    }
+    write_le(&mut output, start.0)?;
+    write_le(&mut output, start.1)?;
+    write_le(&mut output, track.all_lines().len() as i32)?;
+    for line in track.all_lines() {
+        write_line(&mut output, line, &features)?;
+    }
+    if !metadata.is_empty() {
+        output.extend(META_MAGIC);
+        write_le(&mut output, metadata.len() as i16)?;
+        for (key, value) in metadata {
+            write_short_string(&mut output, &format!("{key}={value}"))?;
+        }
+    }
+
+    Ok(output)
+}
+
+/// The `META` entries for the gravity and gravity well size of `track` that are not Line
+/// Rider's, or an error if gravity does not read back the same once stored as a multiple.
+fn physics_metadata(track: &Track) -> Result<Vec<(&'static str, f64)>> {
+    let gravity = track.gravity();
+    let mut metadata = vec![];
+    for (key, component, default) in [
+        (META_X_GRAVITY, gravity.0, DEFAULT_GRAVITY.0),
+        (META_Y_GRAVITY, gravity.1, DEFAULT_GRAVITY.1),
+    ] {
+        if component == default {
+            continue;
+        }
+        let multiple = component / DEFAULT_GRAVITY.1;
+        if multiple * DEFAULT_GRAVITY.1 != component {
+            bail!(".trk cannot represent gravity {} exactly", component);
+        }
+        metadata.push((key, multiple));
+    }
+
+    let gravity_well_height = track.gravity_well_height();
+    if gravity_well_height != TrackMeta::default().gravity_well_height {
+        metadata.push((META_GRAVITY_WELL_SIZE, gravity_well_height));
+    }
+
+    Ok(metadata)
+}
+
+/// The bosh sled that `.trk` files start with, translated to `start`.
+fn start_rider(start: Vector2D, zero_start: bool, remount: bool) -> Entity {
+    let velocity = if zero_start {
+        Vector2D(0.0, 0.0)
+    } else {
+        DEFAULT_START_VELOCITY
+    };
+    let mut rider = Entity::starting_boshsled(start, velocity, 0.0);
+    rider.options.remountable = remount;
+
+    rider
+}
+
+/// Finds the start position, whether the rider has zero velocity and whether it can
+/// remount, or returns an error if the rider is not a `.trk` starting rider.
+fn rider_start(rider: &Entity) -> Result<(Vector2D, bool, bool)> {
+    let start = rider
+        .points
+        .get(&PointIndex::SledPeg)
+        .context(".trk requires the rider to have a sled")?
+        .location;
+
+    let default_options = RiderOptions::default();
+    if rider.options.gravity_scale != default_options.gravity_scale
+        || rider.options.mount_endurance_scale != default_options.mount_endurance_scale
+        || rider.options.invincible != default_options.invincible
+    {
+        bail!(".trk cannot represent rider gravity, mount endurance or invincibility");
+    }
+
+    let remount = rider.options.remountable;
+    if *rider == start_rider(start, false, remount) {
+        Ok((start, false, remount))
+    } else if *rider == start_rider(start, true, remount) {
+        Ok((start, true, remount))
+    } else {
+        bail!(".trk cannot represent a rider that is not in its starting pose")
+    }
+}
+
+fn write_features(features: &TrkFeatures) -> String {
+    [
+        (features.red_multiplier, FEATURE_RED_MULTIPLIER),
+        (features.scenery_width, FEATURE_SCENERY_WIDTH),
+        (features.six_one, FEATURE_SIX_ONE),
+        (features.song_info, FEATURE_SONG_INFO),
+        (features.ignorable_trigger, FEATURE_IGNORABLE_TRIGGER),
+        (features.zero_start, FEATURE_ZERO_START),
+        (features.remount, FEATURE_REMOUNT),
+        (features.frictionless, FEATURE_FRICTIONLESS),
+    ]
+    .into_iter()
+    .filter(|(enabled, _)| *enabled)
+    .map(|(_, feature)| format!("{feature};"))
+    .collect()
+}
+
+fn write_line(output: &mut impl Write, line: &Line, features: &TrkFeatures) -> Result<()> {
+    let extension = line.ends.0.extended as u8 | (line.ends.1.extended as u8) << 1;
+    let line_type = match line.line_type {
+        LineType::Normal => LINE_TYPE_NORMAL,
+        LineType::Accelerate { .. } => LINE_TYPE_ACCELERATE,
+        LineType::Scenery { .. } => LINE_TYPE_SCENERY,
+    };
+    let flags = (line.flipped as u8) << 7 | extension << 5 | line_type;
+    flags.write_to(&mut *output)?;
+
+    match line.line_type {
+        LineType::Accelerate { amount } if features.red_multiplier => {
+            (amount as u8).write_to(&mut *output)?;
+        }
+        LineType::Scenery { width } if features.scenery_width => {
+            ((width * SCENERY_WIDTH_SCALE).round() as u8).write_to(&mut *output)?;
+        }
+        _ => {}
+    }
+
+    if line_type != LINE_TYPE_SCENERY {
+        write_le(output, line.id as i32)?;
+        if extension != 0 {
+            write_le(output, adjacent_id(line.left_line))?;
+            write_le(output, adjacent_id(line.right_line))?;
+        }
+    }
+
+    write_le(output, line.ends.0.location.0)?;
+    write_le(output, line.ends.0.location.1)?;
+    write_le(output, line.ends.1.location.0)?;
+    write_le(output, line.ends.1.location.1)?;
+
+    Ok(())
+}
+
+fn read_features(feature_string: &str) -> Result<TrkFeatures> {
+    let mut features = TrkFeatures::default();
+    for feature in feature_string.split(';').filter(|f| !f.is_empty()) {
+        match feature {
+            FEATURE_RED_MULTIPLIER => features.red_multiplier = true,
+            FEATURE_SCENERY_WIDTH => features.scenery_width = true,
+            FEATURE_SIX_ONE => features.six_one = true,
+            FEATURE_SONG_INFO => features.song_info = true,
+            FEATURE_IGNORABLE_TRIGGER => features.ignorable_trigger = true,
+            FEATURE_ZERO_START => features.zero_start = true,
+            FEATURE_REMOUNT => features.remount = true,
+            FEATURE_FRICTIONLESS => features.frictionless = true,
+            other => bail!("unsupported .trk feature {:?}", other),
+        }
+    }
+
+    Ok(features)
+}
+
+/// Songs are stored as `name\r\noffset`.
+fn read_song(song: &str) -> Result<Song> {
+    let (name, offset) = song
+        .split_once("\r\n")
+        .with_context(|| format!("malformed song info {song:?}"))?;
+
+    Ok(Song {
+        name: name.to_string(),
+        offset: offset
+            .trim_end_matches("\r\n")
+            .parse()
+            .with_context(|| format!("malformed song offset {offset:?}"))?,
+    })
+}
+
+fn read_line(
+    input: &mut impl Read,
+    track: &Track,
+    features: &TrkFeatures,
+    next_scenery_id: &mut i64,
+) -> Result<Line> {
+    let flags = u8::read_from(&mut *input).context("error while reading line flags")?;
+    let line_type = flags & 0x1F;
+    let flipped = flags >> 7 != 0;
+    let extension = (flags >> 5) & 0x3;
+
+    let mut multiplier = 1;
+    if line_type == LINE_TYPE_ACCELERATE && features.red_multiplier {
+        multiplier = u8::read_from(&mut *input).context("error while reading multiplier")?;
+    }
+    let mut width = DEFAULT_SCENERY_WIDTH;
+
+    let mut left_line = None;
+    let mut right_line = None;
+    let id = match line_type {
+        LINE_TYPE_NORMAL | LINE_TYPE_ACCELERATE => {
+            if features.ignorable_trigger {
+                let has_zoom_trigger =
+                    u8::read_from(&mut *input).context("error while reading trigger flag")?;
+                if has_zoom_trigger != 0 {
+                    // zoom target and frame count; not relevant to physics
+                    let _: f32 = read_le(input, "zoom trigger target")?;
+                    let _: i16 = read_le(input, "zoom trigger frames")?;
+                }
+            }
+
+            let id: i32 = read_le(input, "line id")?;
+            if extension != 0 {
+                let left: i32 = read_le(input, "previous line id")?;
+                let right: i32 = read_le(input, "next line id")?;
+                left_line = Some(left as i64).filter(|id| *id >= 0);
+                right_line = Some(right as i64).filter(|id| *id >= 0);
+            }
+
+            id as i64
+        }
+        LINE_TYPE_SCENERY => {
+            if features.scenery_width {
+                let stored =
+                    u8::read_from(&mut *input).context("error while reading scenery width")?;
+                width = stored as f64 / SCENERY_WIDTH_SCALE;
+            }
+
+            let id = *next_scenery_id;
+            *next_scenery_id -= 1;
+            id
+        }
+        other => bail!("unknown .trk line type {}", other),
+    };
+
+    let p1 = Vector2D::read_from(&mut *input).context("error while reading line start")?;
+    let p2 = Vector2D::read_from(&mut *input).context("error while reading line end")?;
+
+    let line_type = match line_type {
+        LINE_TYPE_NORMAL => LineType::Normal,
+        LINE_TYPE_ACCELERATE => LineType::Accelerate {
+            amount: multiplier as f64,
+        },
+        _ => LineType::Scenery { width },
+    };
+
+    Ok(track
+        .line_builder()
+        .id(id)
+        .line_type(line_type)
+        .flipped(flipped)
+        .left_line(left_line)
+        .right_line(right_line)
+        .point_vec(p1)
+        .extended(extension & 1 != 0)
+        .point_vec(p2)
+        .extended(extension & 2 != 0)
+        .build())
+}
+
+fn read_metadata(input: &mut impl Read) -> Result<Vec<(String, String)>> {
+    let magic: [u8; 4] =
+        ReadFrom::read_from(&mut *input).context("error while reading metadata magic")?;
+    if magic != META_MAGIC {
+        bail!("unexpected data after lines");
+    }
+
+    let count: i16 = read_le(input, "metadata count")?;
+    (0..count)
+        .map(|_| {
+            let entry = read_short_string(input)?;
+            let (key, value) = entry
+                .split_once('=')
+                .with_context(|| format!("malformed metadata entry {entry:?}"))?;
+            Ok((key.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+/// Applies the `META` entries that change physics to `track`. Other entries, such as the
+/// starting zoom or colors, do not affect physics and are left alone.
+fn apply_metadata(track: &mut Track, metadata: &[(String, String)]) -> Result<()> {
+    for (key, value) in metadata {
+        let number = || {
+            value
+                .parse::<f64>()
+                .ok()
+                .filter(|n| n.is_finite())
+                .with_context(|| format!("malformed {key} {value:?}"))
+        };
+        match key.as_str() {
+            META_X_GRAVITY => {
+                let gravity = Vector2D(number()? * DEFAULT_GRAVITY.1, track.gravity().1);
+                track.set_gravity(gravity);
+            }
+            META_Y_GRAVITY => {
+                let gravity = Vector2D(track.gravity().0, number()? * DEFAULT_GRAVITY.1);
+                track.set_gravity(gravity);
+            }
+            META_GRAVITY_WELL_SIZE => track
+                .set_gravity_well_height(number()?)
+                .context("invalid GRAVITYWELLSIZE")?,
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn read_le<T>(input: &mut impl Read, what: &str) -> Result<T>
+where
+    LittleEndian<T>: ReadFrom<Error = io::Error>,
+{
+    Ok(LittleEndian::<T>::read_from(input)
+        .with_context(|| format!("error while reading {what}"))?
+        .0)
+}
+
+/// Missing neighbors are stored as `-1`.
+fn adjacent_id(id: Option<i64>) -> i32 {
+    id.map_or(-1, |id| id as i32)
+}
+
+fn write_le<T>(output: &mut impl Write, value: T) -> Result<()>
+where
+    LittleEndian<T>: WriteTo<Error = io::Error>,
+{
+    LittleEndian(value).write_to(output)?;
+    Ok(())
+}
+
+/// Reads an ASCII string prefixed by its length as an `i16`.
+fn read_short_string(input: &mut impl Read) -> Result<String> {
+    let len: i16 = read_le(input, "string length")?;
+    if len < 0 {
+        bail!("negative string length {}", len);
+    }
+
+    let mut buf = vec![0; len as usize];
+    input
+        .read_exact(&mut buf)
+        .context("error while reading string")?;
+
+    String::from_utf8(buf).context("string is not valid ascii")
+}
+
+/// Reads a UTF-8 string prefixed by its length as a 7-bit encoded integer,
+/// as written by .NET's `BinaryWriter.Write(string)`.
+fn read_dotnet_string(input: &mut impl Read) -> Result<String> {
+    let mut len: usize = 0;
+    for shift in (0..35).step_by(7) {
+        let byte = u8::read_from(&mut *input).context("error while reading string length")?;
+        len |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            // the length comes from the file, so only read as much as is actually there
+            let mut buf = vec![];
+            input
+                .take(len as u64)
+                .read_to_end(&mut buf)
+                .context("error while reading string")?;
+            if buf.len() != len {
+                bail!(
+                    "string is {} bytes long, but only {} bytes are left",
+                    len,
+                    buf.len()
+                );
+            }
+            return String::from_utf8(buf).context("string is not valid utf-8");
+        }
+    }
+
+    bail!("string length is too long")
+}
+
+/// Writes an ASCII string prefixed by its length as an `i16`.
+fn write_short_string(output: &mut impl Write, s: &str) -> Result<()> {
+    let len = i16::try_from(s.len()).context("string is too long")?;
+    write_le(output, len)?;
+    output.write_all(s.as_bytes())?;
+
+    Ok(())
+}
+
+/// Writes a UTF-8 string prefixed by its length as a 7-bit encoded integer,
+/// as read by .NET's `BinaryReader.ReadString()`.
+fn write_dotnet_string(output: &mut impl Write, s: &str) -> Result<()> {
+    let mut len = s.len();
+    while len >= 0x80 {
+        output.write_all(&[(len as u8 & 0x7F) | 0x80])?;
+        len >>= 7;
+    }
+    output.write_all(&[len as u8])?;
+    output.write_all(s.as_bytes())?;
+
+    Ok(())
+}
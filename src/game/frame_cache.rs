@@ -0,0 +1,348 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::mem::size_of;
+use std::ops::RangeInclusive;
+
+use crate::linestore::grid::GridIndex;
+use crate::rider::{Bone, Entity, Joint};
+
+/// How a [`Track`](crate::Track) keeps the frames it has simulated.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum CachePolicy {
+    /// Keeps every frame that was simulated.
+    #[default]
+    EveryFrame,
+    /// Keeps every `interval`th frame and re-simulates the frames in between from the
+    /// closest earlier checkpoint. The most recently requested frame is also kept, so
+    /// playing a track forward does not re-simulate anything. While a thread simulates, only
+    /// the frames that other threads wait for are handed over to them, so a thread that asks
+    /// for a frame the simulation already passed waits for it to end and simulates again
+    /// from the closest checkpoint.
+    Checkpoints {
+        interval: usize,
+        /// Roughly how many bytes the checkpoints may take up, or `None` for no limit.
+        memory_budget: Option<usize>,
+        eviction: Eviction,
+    },
+}
+
+/// Which checkpoints to drop once the memory budget of [`CachePolicy::Checkpoints`] is used up.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Eviction {
+    /// Drops the checkpoint that was used least recently.
+    #[default]
+    LeastRecentlyUsed,
+    /// Drops every other checkpoint and doubles the interval for new ones, keeping the
+    /// checkpoints spread evenly over the track.
+    Thin,
+}
+
+/// A frame kept by the cache.
+#[derive(Clone, Debug)]
+struct StoredFrame {
+    entities: Vec<Entity>,
+    size: usize,
+    last_used: u64,
+}
+
+/// Simulated frames, along with which frames searched for lines in which grid cells.
+#[derive(Clone, Debug)]
+pub(crate) struct FrameCache {
+    policy: CachePolicy,
+    /// The interval between checkpoints, which grows when [`Eviction::Thin`] evicts.
+    interval: usize,
+    /// Stored frames by index. Frame 0 holds the starting positions and is always kept.
+    frames: BTreeMap<usize, StoredFrame>,
+    /// The total estimated size of `frames`.
+    size: usize,
+    /// The most recently requested frame, if it is not stored.
+    cursor: Option<(usize, Vec<Entity>)>,
+    /// Increases every time a frame is used, to find the least recently used one.
+    clock: u64,
+    /// For each cell that collision queries searched around, the first frame that did so.
+    /// Frames before it do not depend on the lines near that cell.
+    first_query_frames: HashMap<GridIndex, usize>,
+    /// The frames that a thread is simulating, if one is.
+    simulating: Option<RangeInclusive<usize>>,
+    /// Frames that threads wait for while another thread simulates them, with the frame
+    /// once it is simulated, so that waiters get it even if the policy does not store it.
+    awaited: HashMap<usize, AwaitedFrame>,
+    /// How many frames have been simulated, including frames simulated again.
+    simulated_count: u64,
+}
+
+/// A frame that threads wait for another thread to simulate.
+#[derive(Clone, Debug)]
+struct AwaitedFrame {
+    waiters: usize,
+    entities: Option<Vec<Entity>>,
+}
+
+impl FrameCache {
+    pub(crate) fn new(starting_positions: Vec<Entity>, policy: CachePolicy) -> FrameCache {
+        let mut cache = FrameCache {
+            policy,
+            interval: 1,
+            frames: BTreeMap::new(),
+            size: 0,
+            cursor: None,
+            clock: 0,
+            first_query_frames: HashMap::new(),
+            simulating: None,
+            awaited: HashMap::new(),
+            simulated_count: 0,
+        };
+        cache.set_policy(policy);
+        cache.insert(0, starting_positions);
+
+        cache
+    }
+
+    /// A copy of the cache without the simulation in progress, if any, which only the
+    /// original cache will hear of.
+    pub(crate) fn settled_clone(&self) -> FrameCache {
+        FrameCache {
+            simulating: None,
+            awaited: HashMap::new(),
+            ..self.clone()
+        }
+    }
+
+    pub(crate) fn policy(&self) -> CachePolicy {
+        self.policy
+    }
+
+    /// Switches to another policy, keeping only the starting positions.
+    pub(crate) fn set_policy(&mut self, policy: CachePolicy) {
+        self.policy = policy;
+        self.interval = match policy {
+            CachePolicy::EveryFrame => 1,
+            CachePolicy::Checkpoints { interval, .. } => interval.max(1),
+        };
+        self.invalidate_from(1);
+    }
+
+    /// The number of stored frames, including the starting positions.
+    pub(crate) fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub(crate) fn starting_positions_mut(&mut self) -> &mut Vec<Entity> {
+        &mut self
+            .frames
+            .get_mut(&0)
+            .expect("starting positions are always kept")
+            .entities
+    }
+
+    /// Gets a frame if it is stored or was the last one requested.
+    pub(crate) fn get(&mut self, frame: usize) -> Option<Vec<Entity>> {
+        if let Some((cursor, entities)) = &self.cursor {
+            if *cursor == frame {
+                return Some(entities.clone());
+            }
+        }
+
+        self.clock += 1;
+        let stored = self.frames.get_mut(&frame)?;
+        stored.last_used = self.clock;
+        Some(stored.entities.clone())
+    }
+
+    /// Gets the closest frame before `frame` to simulate forward from.
+    pub(crate) fn closest_before(&mut self, frame: usize) -> (usize, Vec<Entity>) {
+        self.clock += 1;
+        let (&index, stored) = self
+            .frames
+            .range_mut(..frame)
+            .next_back()
+            .expect("starting positions are always kept");
+        stored.last_used = self.clock;
+
+        match &self.cursor {
+            Some((cursor, entities)) if index < *cursor && *cursor < frame => {
+                (*cursor, entities.clone())
+            }
+            _ => (index, stored.entities.clone()),
+        }
+    }
+
+    /// Remembers a newly simulated frame and the cells that its collision queries searched.
+    /// Returns whether a thread was waiting for the frame.
+    pub(crate) fn simulated(
+        &mut self,
+        frame: usize,
+        entities: &[Entity],
+        queried_cells: impl IntoIterator<Item = GridIndex>,
+    ) -> bool {
+        self.simulated_count += 1;
+        for cell in queried_cells {
+            self.first_query_frames.entry(cell).or_insert(frame);
+        }
+        if frame.is_multiple_of(self.interval) && !self.frames.contains_key(&frame) {
+            self.insert(frame, entities.to_vec());
+            self.evict();
+        }
+
+        match self.awaited.get_mut(&frame) {
+            Some(awaited) => {
+                awaited.entities = Some(entities.to_vec());
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn simulated_count(&self) -> u64 {
+        self.simulated_count
+    }
+
+    /// The frames that a thread is simulating, if one is.
+    pub(crate) fn simulating(&self) -> Option<RangeInclusive<usize>> {
+        self.simulating.clone()
+    }
+
+    /// Marks that a thread is simulating `frames`, or that it stopped with `None`.
+    pub(crate) fn set_simulating(&mut self, frames: Option<RangeInclusive<usize>>) {
+        self.simulating = frames;
+    }
+
+    /// Marks that a thread waits for the thread that is simulating to reach `frame`.
+    pub(crate) fn await_frame(&mut self, frame: usize) {
+        self.awaited
+            .entry(frame)
+            .or_insert(AwaitedFrame {
+                waiters: 0,
+                entities: None,
+            })
+            .waiters += 1;
+    }
+
+    /// Whether a frame that threads wait for has been simulated.
+    pub(crate) fn awaited_ready(&self, frame: usize) -> bool {
+        self.awaited
+            .get(&frame)
+            .is_some_and(|awaited| awaited.entities.is_some())
+    }
+
+    /// Stops waiting for `frame`, returning it if it has been simulated.
+    pub(crate) fn leave_awaited(&mut self, frame: usize) -> Option<Vec<Entity>> {
+        let awaited = self.awaited.get_mut(&frame)?;
+        awaited.waiters -= 1;
+        if awaited.waiters == 0 {
+            self.awaited.remove(&frame)?.entities
+        } else {
+            awaited.entities.clone()
+        }
+    }
+
+    /// Roughly how many bytes the stored frames and the frames handed over to waiting
+    /// threads take up.
+    pub(crate) fn estimated_size(&self) -> usize {
+        self.size
+            + self
+                .awaited
+                .values()
+                .filter_map(|awaited| awaited.entities.as_deref())
+                .map(estimated_size)
+                .sum::<usize>()
+    }
+
+    /// Keeps the frame that was just requested, so that the next one is quick to simulate.
+    pub(crate) fn set_cursor(&mut self, frame: usize, entities: &[Entity]) {
+        self.cursor = if self.frames.contains_key(&frame) {
+            None
+        } else {
+            Some((frame, entities.to_vec()))
+        };
+    }
+
+    /// Drops `frame` and every frame after it, except for the starting positions.
+    pub(crate) fn invalidate_from(&mut self, frame: usize) {
+        let frame = frame.max(1);
+        for (_, stored) in self.frames.split_off(&frame) {
+            self.size -= stored.size;
+        }
+        if matches!(self.cursor, Some((cursor, _)) if cursor >= frame) {
+            self.cursor = None;
+        }
+        self.first_query_frames.retain(|_, first| *first < frame);
+    }
+
+    /// Drops every frame from the first one whose collision queries searched `cells`.
+    pub(crate) fn invalidate_cells(&mut self, cells: &HashSet<GridIndex>) {
+        let first = cells
+            .iter()
+            .filter_map(|cell| self.first_query_frames.get(cell))
+            .min();
+        if let Some(&first) = first {
+            self.invalidate_from(first);
+        }
+    }
+
+    fn insert(&mut self, frame: usize, entities: Vec<Entity>) {
+        self.clock += 1;
+        let size = estimated_size(&entities);
+        self.size += size;
+        self.frames.insert(
+            frame,
+            StoredFrame {
+                entities,
+                size,
+                last_used: self.clock,
+            },
+        );
+    }
+
+    /// Drops checkpoints until they fit in the memory budget.
+    fn evict(&mut self) {
+        let (memory_budget, eviction) = match self.policy {
+            CachePolicy::Checkpoints {
+                memory_budget: Some(memory_budget),
+                eviction,
+                ..
+            } => (memory_budget, eviction),
+            _ => return,
+        };
+
+        while self.size > memory_budget && self.frames.len() > 1 {
+            let evicted: Vec<usize> = match eviction {
+                Eviction::LeastRecentlyUsed => self
+                    .frames
+                    .iter()
+                    .skip(1)
+                    .min_by_key(|(_, stored)| stored.last_used)
+                    .map(|(&frame, _)| frame)
+                    .into_iter()
+                    .collect(),
+                Eviction::Thin => {
+                    self.interval *= 2;
+                    let interval = self.interval;
+                    self.frames
+                        .keys()
+                        .copied()
+                        .filter(|frame| !frame.is_multiple_of(interval))
+                        .collect()
+                }
+            };
+
+            for frame in evicted {
+                if let Some(stored) = self.frames.remove(&frame) {
+                    self.size -= stored.size;
+                }
+            }
+        }
+    }
+}
+
+/// Estimates how many bytes a frame takes up in memory.
+fn estimated_size(entities: &[Entity]) -> usize {
+    size_of::<StoredFrame>()
+        + entities
+            .iter()
+            .map(|entity| {
+                size_of::<Entity>()
+                    + entity.bones.capacity() * size_of::<Bone>()
+                    + entity.joints.capacity() * size_of::<Joint>()
+            })
+            .sum::<usize>()
+}
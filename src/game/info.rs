@@ -0,0 +1,26 @@
+use serde::{Deserialize, Serialize};
+
+/// Information about a track which does not affect physics.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct TrackInfo {
+    pub title: String,
+    pub creator: String,
+    pub description: String,
+    /// Length of the track in frames, if the author set one.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub duration: Option<u64>,
+    /// The version tag the track was saved with, such as `"6.2"`.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub song: Option<Song>,
+}
+
+/// A song that plays alongside a track.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Song {
+    /// File name of the song.
+    pub name: String,
+    /// Offset into the song at frame 0, in seconds.
+    pub offset: f64,
+}
@@ -1,24 +1,83 @@
 use std::hash::{Hash, Hasher};
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
 use crate::game::vector::Vector2D;
 
-#[derive(Copy, Clone, Hash, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, Serialize, Default)]
 pub enum LineType {
+    #[default]
     Normal,
-    // Suggestion: Acceleration amounts from linerider.com are f64 values
-    Accelerate { amount: u64 },
-    // Suggestion: Not pertinent to physics, but scenery lines can have f64 width values
-    Scenery,
+    /// Pushes riders along the line. `amount` is the multiplier, which may be fractional
+    /// or zero.
+    Accelerate { amount: f64 },
+    /// Drawn but never collided with. `width` scales the thickness the line is drawn with.
+    Scenery { width: f64 },
 }
 
-impl Default for LineType {
-    fn default() -> Self {
-        LineType::Normal
+/// The width of scenery lines in tracks that do not store one.
+pub const DEFAULT_SCENERY_WIDTH: f64 = 1.0;
+
+impl<'de> Deserialize<'de> for LineType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(rename = "LineType")]
+        enum Tagged {
+            Normal,
+            Accelerate { amount: f64 },
+            Scenery { width: f64 },
+        }
+
+        /// Lines serialized before scenery lines had a width store `"Scenery"` alone.
+        #[derive(Deserialize)]
+        enum Legacy {
+            Scenery,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Compat {
+            Tagged(Tagged),
+            Legacy(Legacy),
+        }
+
+        Ok(match Compat::deserialize(deserializer)? {
+            Compat::Tagged(Tagged::Normal) => LineType::Normal,
+            Compat::Tagged(Tagged::Accelerate { amount }) => LineType::Accelerate { amount },
+            Compat::Tagged(Tagged::Scenery { width }) => LineType::Scenery { width },
+            Compat::Legacy(Legacy::Scenery) => LineType::Scenery {
+                width: DEFAULT_SCENERY_WIDTH,
+            },
+        })
+    }
+}
+
+impl PartialEq for LineType {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (LineType::Normal, LineType::Normal) => true,
+            (LineType::Accelerate { amount: a }, LineType::Accelerate { amount: b })
+            | (LineType::Scenery { width: a }, LineType::Scenery { width: b }) => {
+                a.to_bits() == b.to_bits()
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Hash for LineType {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            LineType::Normal => {}
+            LineType::Accelerate { amount } => amount.to_bits().hash(state),
+            LineType::Scenery { width } => width.to_bits().hash(state),
+        }
     }
 }
 
+impl Eq for LineType {}
+
 #[derive(Copy, Clone, Hash, PartialEq, Eq, Debug, Serialize, Deserialize, Default)]
 pub struct LinePoint {
     pub location: Vector2D,
@@ -34,12 +93,19 @@ pub struct Line {
     pub line_type: LineType,
     pub flipped: bool,
 
+    /// The id of the line connected to the first point, if any.
+    #[serde(rename = "leftLine", skip_serializing_if = "Option::is_none", default)]
+    pub left_line: Option<i64>,
+    /// The id of the line connected to the second point, if any.
+    #[serde(rename = "rightLine", skip_serializing_if = "Option::is_none", default)]
+    pub right_line: Option<i64>,
+
     #[serde(skip)] // defined in metadata, constant for all lines
     extension_ratio: f64,
 }
 impl PartialOrd for Line {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.id.partial_cmp(&other.id)
+        Some(self.cmp(other))
     }
 }
 impl Ord for Line {
@@ -55,11 +121,14 @@ impl Default for Line {
             ends: (Default::default(), Default::default()),
             line_type: Default::default(),
             flipped: false,
+            left_line: None,
+            right_line: None,
             extension_ratio: 0.25,
         }
     }
 }
 
+// adjacency is left out of equality so that a line is still found after its neighbor is removed
 impl PartialEq for Line {
     fn eq(&self, other: &Self) -> bool {
         self.id == other.id
@@ -103,6 +172,14 @@ impl LineBuilder {
         self.line.flipped = flipped;
         self
     }
+    pub fn left_line(mut self, left_line: Option<i64>) -> LineBuilder {
+        self.line.left_line = left_line;
+        self
+    }
+    pub fn right_line(mut self, right_line: Option<i64>) -> LineBuilder {
+        self.line.right_line = right_line;
+        self
+    }
     // Suggestion: More explicit documentation/definition for which point is first and which one is second when building lines
     pub fn point(mut self, p1: f64, p2: f64) -> LineBuilder {
         if !self.first_location_init {
@@ -153,6 +230,12 @@ impl Line {
         }
     }
 
+    /// Whether both points of the line have finite coordinates.
+    pub fn has_finite_points(&self) -> bool {
+        let (p1, p2) = (self.ends.0.location, self.ends.1.location);
+        [p1.0, p1.1, p2.0, p2.1].iter().all(|c| c.is_finite())
+    }
+
     pub fn as_vector2d(&self) -> Vector2D {
         self.ends.1.location - self.ends.0.location
     }
@@ -171,6 +254,11 @@ impl Line {
         }
     }
 
+    /// Sets the extension ratio, which is not serialized since it is defined in metadata.
+    pub(crate) fn set_extension_ratio(&mut self, extension_ratio: f64) {
+        self.extension_ratio = extension_ratio;
+    }
+
     pub fn hitbox_extensions(&self) -> (f64, f64) {
         let clamped_len = (self.length_squared().sqrt() * self.extension_ratio).clamp(0.0, 10.0);
         let mut extensions = (0.0, 0.0);
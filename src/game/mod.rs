@@ -1,19 +1,28 @@
+mod frame_cache;
+mod info;
 mod line;
 mod track;
 mod vector;
 
+pub use frame_cache::{CachePolicy, Eviction};
+pub use info::*;
 pub use line::*;
 pub use track::*;
 pub use vector::*;
 
 #[cfg(test)]
 mod test {
-    // use lr_formatter_rs::trackjson::read;
-    // use std::fs;
+    use std::fs;
+    use std::sync::{Arc, Barrier};
     use std::vec;
 
-    use crate::rider::PointIndex;
-    use crate::{rider::Entity, Line, LineType, Track, Vector2D};
+    use crate::formats::json::read;
+    use crate::physics::entity_physics::{DISMOUNT_FRAMES, REMOUNT_FRAMES};
+    use crate::rider::{MountState, PointIndex};
+    use crate::{
+        rider::Entity, CachePolicy, Eviction, Line, LineType, PhysicsVersion, Track, TrackMeta,
+        Vector2D, DEFAULT_SCENERY_WIDTH,
+    };
 
     #[test]
     fn test_distance() {
@@ -23,6 +32,13 @@ mod test {
         assert_eq!(vec1.distance_squared(vec2), 25.0)
     }
 
+    #[test]
+    fn test_rotate() {
+        let rotated = Vector2D(2.0, 1.0).rotate_rad(std::f64::consts::FRAC_PI_2);
+        assert!(rotated.distance_squared(Vector2D(-1.0, 2.0)) < 1e-20);
+        assert_eq!(Vector2D(2.0, 1.0).rotate_rad(0.0), Vector2D(2.0, 1.0));
+    }
+
     // Suggestion: Implement entity.avg_position by averaging the position of all entity points (and similar for velocity)
     // Custom function to average entity vectors together
     fn average(entity: &Entity) -> Vector2D {
@@ -33,12 +49,12 @@ mod test {
             result.1 += vector.location.1;
         }
 
-        if entity.points.len() > 0 {
+        if !entity.points.is_empty() {
             result.0 /= entity.points.len() as f64;
             result.1 /= entity.points.len() as f64;
         }
 
-        return result;
+        result
     }
 
     #[test]
@@ -46,8 +62,8 @@ mod test {
         let engine = Track::new(vec![Entity::default_boshsled()], vec![]);
         let entities0 = engine.entity_positions_at(0);
         let entities1 = engine.entity_positions_at(1);
-        let rider0 = entities0.get(0).expect("Rider frame 0 should exist");
-        let rider1 = entities1.get(0).expect("Rider frame 1 should exist");
+        let rider0 = entities0.first().expect("Rider frame 0 should exist");
+        let rider1 = entities1.first().expect("Rider frame 1 should exist");
         let avg_position0 = average(rider0);
         let avg_position1 = average(rider1);
         assert!(
@@ -73,13 +89,13 @@ mod test {
 
         let engine0 = Track::new(vec![Entity::default_boshsled()], vec![]);
         let mut engine1 = engine0.clone();
-        engine1.add_line(line);
+        engine1.add_line(line).unwrap();
 
         let entities0 = engine0.entity_positions_at(frame);
         let entities1 = engine1.entity_positions_at(frame);
 
-        let rider_falling = entities0.get(0).expect("Rider falling should exist");
-        let rider_colliding = entities1.get(0).expect("Rider colliding should exist");
+        let rider_falling = entities0.first().expect("Rider falling should exist");
+        let rider_colliding = entities1.first().expect("Rider colliding should exist");
 
         let rider_falling_points = average(rider_falling);
         let rider_colliding_points = average(rider_colliding);
@@ -114,7 +130,7 @@ mod test {
 
         let engine = Track::new(vec![Entity::default_boshsled()], vec![line1, line2]);
         let entities = engine.entity_positions_at(frame);
-        let rider = entities.get(0).expect("Rider should exist");
+        let rider = entities.first().expect("Rider should exist");
         let sled = entities
             .get(1)
             .expect("Sled should exist as separate entity");
@@ -148,7 +164,9 @@ mod test {
     fn scenery_line() {
         let line = Line::builder()
             .id(0)
-            .line_type(LineType::Scenery)
+            .line_type(LineType::Scenery {
+                width: DEFAULT_SCENERY_WIDTH,
+            })
             .point(0.0, 5.0)
             .point(30.0, 5.0)
             .build();
@@ -156,13 +174,13 @@ mod test {
 
         let engine0 = Track::new(vec![Entity::default_boshsled()], vec![]);
         let mut engine1 = engine0.clone();
-        engine1.add_line(line);
+        engine1.add_line(line).unwrap();
 
         let entities0 = engine0.entity_positions_at(frame);
         let entities1 = engine1.entity_positions_at(frame);
 
-        let rider_falling = entities0.get(0).expect("Rider falling should exist");
-        let rider_colliding = entities1.get(0).expect("Rider colliding should exist");
+        let rider_falling = entities0.first().expect("Rider falling should exist");
+        let rider_colliding = entities1.first().expect("Rider colliding should exist");
 
         let rider_falling_points = average(rider_falling);
         let rider_colliding_points = average(rider_colliding);
@@ -183,7 +201,7 @@ mod test {
             .build();
         let acc_line = Line::builder()
             .id(0)
-            .line_type(LineType::Accelerate { amount: 1 })
+            .line_type(LineType::Accelerate { amount: 1.0 })
             .point(0.0, 5.0)
             .point(30.0, 5.0)
             .build();
@@ -192,14 +210,14 @@ mod test {
         let engine = Track::new(vec![Entity::default_boshsled()], vec![]);
         let mut engine0 = engine.clone();
         let mut engine1 = engine.clone();
-        engine0.add_line(line);
-        engine1.add_line(acc_line);
+        engine0.add_line(line).unwrap();
+        engine1.add_line(acc_line).unwrap();
 
         let entities0 = engine0.entity_positions_at(frame);
         let entities1 = engine1.entity_positions_at(frame);
 
-        let rider_moving = entities0.get(0).expect("Rider moving should exist");
-        let rider_speeding = entities1.get(0).expect("Rider speeding should exist");
+        let rider_moving = entities0.first().expect("Rider moving should exist");
+        let rider_speeding = entities1.first().expect("Rider speeding should exist");
 
         let rider_moving_points = average(rider_moving);
         let rider_speeding_points = average(rider_speeding);
@@ -220,7 +238,7 @@ mod test {
             .build();
         let acc_line = Line::builder()
             .id(0)
-            .line_type(LineType::Accelerate { amount: 1 })
+            .line_type(LineType::Accelerate { amount: 1.0 })
             .point(30.0, 5.0)
             .point(0.0, 5.0)
             .flipped(true)
@@ -230,55 +248,606 @@ mod test {
         let engine = Track::new(vec![Entity::default_boshsled()], vec![]);
         let mut engine0 = engine.clone();
         let mut engine1 = engine.clone();
-        engine0.add_line(line);
-        engine1.add_line(acc_line);
+        engine0.add_line(line).unwrap();
+        engine1.add_line(acc_line).unwrap();
 
         let entities0 = engine0.entity_positions_at(frame);
         let entities1 = engine1.entity_positions_at(frame);
 
-        let rider_moving = entities0.get(0).expect("Rider moving should exist");
-        let rider_reversing = entities1.get(0).expect("Rider reversing should exist");
+        let rider_moving = entities0.first().expect("Rider moving should exist");
+        let rider_reversing = entities1.first().expect("Rider reversing should exist");
+
+        let rider_moving_points = average(rider_moving);
+        let rider_reversing_points = average(rider_reversing);
+
+        assert!(
+            rider_moving_points.0 > rider_reversing_points.0,
+            "Rider should have moved backwards"
+        );
+    }
+
+    /// Rides `fixtures/accel_directions.track.json`, which pairs a rider on a normal line with
+    /// one on an acceleration line for each endpoint order and solid side. Frames exported
+    /// from linerider.com for this fixture are still to be added; until then the test only
+    /// checks the direction of acceleration against the normal lines.
+    #[test]
+    fn accel_line_directions() {
+        let track_bytes = fs::read_to_string("./fixtures/accel_directions.track.json")
+            .expect("Failed to read file");
+        let track = read(&track_bytes).expect("Failed to parse file");
+        let entities = track.entity_positions_at(12);
+        assert_eq!(entities.len(), 8, "no rider should have crashed");
+
+        // relative to its own start, as the riders are spaced apart
+        let travelled = |rider: usize| {
+            entities[rider]
+                .point_at(PointIndex::BoshShoulder)
+                .location
+                .0
+                - rider as f64 * 200.0
+        };
+        // acceleration pushes towards the second endpoint, whichever side is solid: the
+        // pairs fall onto lines drawn left to right and right to left, then rise into them
+        // the same way
+        for (pair, forwards) in [(0, true), (1, false), (2, true), (3, false)] {
+            let (normal, accel) = (travelled(2 * pair), travelled(2 * pair + 1));
+            if forwards {
+                assert!(accel > normal, "pair {pair}");
+            } else {
+                assert!(accel < normal, "pair {pair}");
+            }
+        }
+    }
+
+    #[test]
+    fn crash() {
+        let track_bytes =
+            fs::read_to_string("./fixtures/crash.track.json").expect("Failed to read file");
+        let track = read(&track_bytes).expect("Failed to parse file");
+        assert_eq!(track.info.title, "cripple");
+        assert_eq!(track.all_lines().len(), 4);
+    }
+
+    #[test]
+    fn cycloid() {
+        let track_bytes =
+            fs::read_to_string("./fixtures/cycloid.track.json").expect("Failed to read file");
+        let track = read(&track_bytes).expect("Failed to parse file");
+        assert_eq!(track.info.title, "cycloid");
+        assert_eq!(track.all_lines().len(), 645);
+    }
+
+    #[test]
+    fn legacy_test() {
+        let track_bytes = fs::read_to_string("./fixtures/legacyTestTrack.track.json")
+            .expect("Failed to read file");
+        let track = read(&track_bytes).expect("Failed to parse file");
+        assert_eq!(track.info.title, "legacyTestTrack");
+        assert_eq!(track.all_lines().len(), 551);
+        assert_eq!(track.physics_version(), PhysicsVersion::SixOne);
+    }
+
+    #[test]
+    fn remount() {
+        let track_bytes =
+            fs::read_to_string("./fixtures/remount.track.json").expect("Failed to read file");
+        let track = read(&track_bytes).expect("Failed to parse file");
+
+        // frames where the mount phase changes, as ridden by this crate. The same track
+        // opens in LRA-CE, where these frames have not been checked yet
+        let mut changes = vec![];
+        let mut state = MountState::Mounted;
+        for frame in 0..=320 {
+            let entities = track.entity_positions_at(frame);
+            assert_eq!(entities.len(), 1, "frame {frame}");
+            let phase_changed =
+                std::mem::discriminant(&entities[0].mount_state) != std::mem::discriminant(&state);
+            if phase_changed {
+                state = entities[0].mount_state;
+                changes.push((frame, state));
+            }
+        }
+
+        let dismount = 73;
+        let remount = 158;
+        assert_eq!(
+            changes,
+            vec![
+                (
+                    dismount,
+                    MountState::Dismounting {
+                        frames_left: DISMOUNT_FRAMES
+                    }
+                ),
+                (dismount + DISMOUNT_FRAMES as usize, MountState::Dismounted),
+                (
+                    remount,
+                    MountState::Remounting {
+                        frames_left: REMOUNT_FRAMES
+                    }
+                ),
+                (remount + REMOUNT_FRAMES as usize, MountState::Mounted),
+            ]
+        );
+    }
+
+    #[test]
+    fn physics_version_switch() {
+        let track_bytes =
+            fs::read_to_string("./fixtures/testTrack.track.json").expect("Failed to read file");
+        let mut track = read(&track_bytes).expect("Failed to parse file");
+        assert_eq!(track.physics_version(), PhysicsVersion::SixTwo);
+        let six_two = track.entity_positions_at(300);
+
+        track.set_physics_version(PhysicsVersion::SixZero).unwrap();
+        track.entity_positions_at(300);
+        track.set_physics_version(PhysicsVersion::SixTwo).unwrap();
+        assert_eq!(track.entity_positions_at(300), six_two);
+    }
+
+    #[test]
+    fn add_line_errors() {
+        let mut track = Track::new(vec![Entity::default_boshsled()], vec![]);
+        track.set_physics_version(PhysicsVersion::SixZero).unwrap();
+        let long = Line::builder()
+            .point(0.0, 0.0)
+            .point(200000.0, 200000.0)
+            .build();
+        assert!(track.add_line(long).is_err());
+        assert!(track.all_lines().is_empty());
+
+        track.set_physics_version(PhysicsVersion::SixTwo).unwrap();
+        track.add_line(long).unwrap();
+        assert!(track.set_physics_version(PhysicsVersion::SixZero).is_err());
+
+        let not_finite = Line::builder().point(0.0, 0.0).point(f64::NAN, 0.0).build();
+        assert!(track.add_line(not_finite).is_err());
+        assert_eq!(track.all_lines(), &vec![long]);
+    }
+
+    #[test]
+    fn gravity_and_iterations() {
+        let mut track = Track::new(
+            vec![Entity::default_boshsled()],
+            vec![Line::builder().point(0.0, 5.0).point(30.0, 20.0).build()],
+        );
+        let default_frame = track.entity_positions_at(40);
+
+        track.set_gravity(Vector2D(0.0, 0.0875));
+        let mut half_gravity_rider = Entity::default_boshsled();
+        half_gravity_rider.options.gravity_scale = 0.5;
+        let scaled = Track::new(vec![half_gravity_rider], track.all_lines().clone());
+        let half_gravity = track.entity_positions_at(40);
+        assert_ne!(half_gravity, default_frame);
+        assert_eq!(
+            half_gravity[0].points,
+            scaled.entity_positions_at(40)[0].points
+        );
+
+        track.set_iterations(1);
+        assert_ne!(track.entity_positions_at(40), half_gravity);
+
+        let serialized = serde_json::to_string(&track).expect("Failed to serialize track");
+        let deserialized: Track =
+            serde_json::from_str(&serialized).expect("Failed to deserialize track");
+        assert_eq!(deserialized.gravity(), Vector2D(0.0, 0.0875));
+        assert_eq!(deserialized.iterations(), 1);
+
+        track.set_gravity(Vector2D(0.0, 0.175));
+        track.set_iterations(6);
+        assert_eq!(track.entity_positions_at(40), default_frame);
+    }
+
+    #[test]
+    fn meta_change() {
+        let track_bytes =
+            fs::read_to_string("./fixtures/testTrack.track.json").expect("Failed to read file");
+        let mut track = read(&track_bytes).expect("Failed to parse file");
+        let original = track.entity_positions_at(300);
+
+        track.set_cell_size(5.0).unwrap();
+        track.set_gravity_well_height(5.0).unwrap();
+        track.set_line_extension_ratio(0.0).unwrap();
+        assert_eq!(track.cell_size(), 5.0);
+        assert_eq!(track.gravity_well_height(), 5.0);
+        assert_eq!(track.line_extension_ratio(), 0.0);
+        assert!(track
+            .all_lines()
+            .iter()
+            .all(|line| line.hitbox_extensions() == (0.0, 0.0)));
+
+        let lines = track
+            .all_lines()
+            .iter()
+            .map(|line| {
+                let mut line = *line;
+                line.set_extension_ratio(0.0);
+                line
+            })
+            .collect();
+        let fresh = Track::new_with_meta(track.entity_positions_at(0), lines, *track.meta());
+        let changed = track.entity_positions_at(300);
+        assert_ne!(changed, original);
+        assert_eq!(changed, fresh.entity_positions_at(300));
+
+        track.set_meta(TrackMeta::default()).unwrap();
+        assert_eq!(track.entity_positions_at(300), original);
+    }
+
+    #[test]
+    fn invalid_meta() {
+        let mut track = Track::new(
+            vec![],
+            vec![Line::builder().point(0.0, 0.0).point(100.0, 0.0).build()],
+        );
+        for cell_size in [0.0, -14.0, f64::NAN, f64::INFINITY] {
+            assert!(track.set_cell_size(cell_size).is_err());
+        }
+        assert!(track.set_gravity_well_height(0.0).is_err());
+        assert!(track.set_line_extension_ratio(-0.25).is_err());
+        assert_eq!(*track.meta(), TrackMeta::default());
+
+        let mut serialized = serde_json::to_value(&track).unwrap();
+        serialized["meta"]["cellSize"] = 0.0.into();
+        assert!(serde_json::from_value::<Track>(serialized).is_err());
+    }
+
+    #[test]
+    fn partial_invalidation() {
+        let track_bytes =
+            fs::read_to_string("./fixtures/testTrack.track.json").expect("Failed to read file");
+        let mut track = read(&track_bytes).expect("Failed to parse file");
+        let original = track.entity_positions_at(400);
+        assert_eq!(track.cached_frame_count(), 401);
+
+        // lines that no rider came near keep every frame
+        let far_away = track
+            .line_builder()
+            .id(10_000)
+            .point(50_000.0, 50_000.0)
+            .point(50_100.0, 50_000.0)
+            .build();
+        track.add_line(far_away).unwrap();
+        assert_eq!(track.cached_frame_count(), 401);
+        track.remove_line(&far_away);
+        assert_eq!(track.cached_frame_count(), 401);
+
+        let mut partially_kept = 0;
+        for line in track.all_lines().clone().into_iter().step_by(25) {
+            track.remove_line(&line);
+            let kept = track.cached_frame_count();
+            if 1 < kept && kept < 401 {
+                partially_kept += 1;
+            }
+
+            let fresh = Track::new_with_meta(
+                track.entity_positions_at(0),
+                track.all_lines().clone(),
+                *track.meta(),
+            );
+            assert_eq!(
+                track.entity_positions_at(400),
+                fresh.entity_positions_at(400),
+                "line {}",
+                line.id
+            );
+
+            track.add_line(line).unwrap();
+            assert_eq!(track.entity_positions_at(400), original, "line {}", line.id);
+        }
+        assert!(partially_kept > 0);
+    }
+
+    #[test]
+    fn checkpoint_cache() {
+        let track_bytes =
+            fs::read_to_string("./fixtures/testTrack.track.json").expect("Failed to read file");
+        let full = read(&track_bytes).expect("Failed to parse file");
+        let expected: Vec<_> = (0..=150).map(|f| full.entity_positions_at(f)).collect();
+
+        let policies = [
+            CachePolicy::Checkpoints {
+                interval: 10,
+                memory_budget: None,
+                eviction: Eviction::LeastRecentlyUsed,
+            },
+            CachePolicy::Checkpoints {
+                interval: 7,
+                memory_budget: Some(16 * 1024),
+                eviction: Eviction::LeastRecentlyUsed,
+            },
+            CachePolicy::Checkpoints {
+                interval: 3,
+                memory_budget: Some(16 * 1024),
+                eviction: Eviction::Thin,
+            },
+        ];
+        // playing forwards, then jumping around
+        let order: Vec<usize> = (0..=150).chain((0..150).map(|i| i * 37 % 151)).collect();
+
+        for policy in policies {
+            let mut track = full.clone();
+            track.set_cache_policy(policy);
+            assert_eq!(track.cache_policy(), policy);
+            assert_eq!(track.cached_frame_count(), 1);
+
+            for &frame in &order {
+                assert_eq!(
+                    track.entity_positions_at(frame),
+                    expected[frame],
+                    "{policy:?} frame {frame}"
+                );
+            }
+            if let CachePolicy::Checkpoints {
+                interval,
+                memory_budget: Some(_),
+                ..
+            } = policy
+            {
+                assert!(track.cached_frame_count() < 150 / interval);
+            }
+
+            // edited tracks still ride like freshly built ones
+            let line = track.all_lines()[40];
+            track.remove_line(&line);
+            let fresh = Track::new_with_meta(
+                track.entity_positions_at(0),
+                track.all_lines().clone(),
+                *track.meta(),
+            );
+            assert_eq!(
+                track.entity_positions_at(150),
+                fresh.entity_positions_at(150)
+            );
+            track.add_line(line).unwrap();
+            assert_eq!(track.entity_positions_at(150), expected[150]);
+        }
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Track>();
+
+        let track_bytes =
+            fs::read_to_string("./fixtures/testTrack.track.json").expect("Failed to read file");
+        let sequential = read(&track_bytes).expect("Failed to parse file");
+        let expected: Vec<_> = (0..=100)
+            .map(|frame| sequential.entity_positions_at(frame))
+            .collect();
+
+        let track = Arc::new(read(&track_bytes).expect("Failed to parse file"));
+        let threads: Vec<_> = (0..4)
+            .map(|thread| {
+                let track = Arc::clone(&track);
+                std::thread::spawn(move || {
+                    (0..=100)
+                        .map(|i| (i * (thread + 1) * 13) % 101)
+                        .map(|frame| (frame, track.entity_positions_at(frame)))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            for (frame, riders) in thread.join().unwrap() {
+                assert_eq!(riders, expected[frame]);
+            }
+        }
+        assert_eq!(track.cached_frame_count(), 101);
+    }
 
-        let _rider_moving_points = average(rider_moving);
-        let _rider_reversing_points = average(rider_reversing);
+    #[test]
+    fn cached_read_while_simulating() {
+        let track_bytes =
+            fs::read_to_string("./fixtures/testTrack.track.json").expect("Failed to read file");
+        let track = Arc::new(read(&track_bytes).expect("Failed to parse file"));
+        let cached = track.entity_positions_at(50);
+
+        let simulating = {
+            let track = Arc::clone(&track);
+            std::thread::spawn(move || track.entity_positions_at(3000))
+        };
+        while track.cached_frame_count() <= 51 {
+            std::thread::yield_now();
+        }
 
-        // FAILING
+        // the read returns while the other thread still has thousands of frames to go
+        assert_eq!(track.entity_positions_at(50), cached);
+        assert!(track.cached_frame_count() < 3001);
 
-        // assert!(
-        //     rider_moving_points.0 > rider_reversing_points.0,
-        //     "Rider should have moved backwards"
-        // );
+        simulating.join().unwrap();
+        assert_eq!(track.cached_frame_count(), 3001);
     }
 
-    //     #[test]
-    //     fn crash() {
-    //         let track_bytes =
-    //             fs::read_to_string("./fixtures/crash.track.json").expect("Failed to read file");
-    //         let track = read(&track_bytes).expect("Failed to parse file");
-    //         println!("{}", track.title);
-    //     }
-    //
-    //     #[test]
-    //     fn cycloid() {
-    //         let track_bytes =
-    //             fs::read_to_string("./fixtures/cycloid.track.json").expect("Failed to read file");
-    //         let track = read(&track_bytes).expect("Failed to parse file");
-    //         println!("{}", track.title);
-    //     }
-    //
-    //     #[test]
-    //     fn legacy_test() {
-    //         let track_bytes = fs::read_to_string("./fixtures/legacyTestTrack.track.json")
-    //             .expect("Failed to read file");
-    //         let track = read(&track_bytes).expect("Failed to parse file");
-    //         println!("{}", track.title);
-    //     }
-    //
-    //     #[test]
-    //     fn modern_test() {
-    //         let track_bytes =
-    //             fs::read_to_string("./fixtures/testTrack.track.json").expect("Failed to read file");
-    //         let track = read(&track_bytes).expect("Failed to parse file");
-    //         println!("{}", track.title);
-    //     }
+    #[test]
+    fn concurrent_reads_simulate_once() {
+        let track_bytes =
+            fs::read_to_string("./fixtures/testTrack.track.json").expect("Failed to read file");
+        let mut track = read(&track_bytes).expect("Failed to parse file");
+        let expected = read(&track_bytes).expect("Failed to parse file");
+
+        for policy in [
+            CachePolicy::EveryFrame,
+            CachePolicy::Checkpoints {
+                interval: 100,
+                memory_budget: None,
+                eviction: Eviction::LeastRecentlyUsed,
+            },
+        ] {
+            track.set_cache_policy(policy);
+            let track = Arc::new(track.clone());
+            let barrier = Arc::new(Barrier::new(3));
+            // 1500 is a checkpoint, so whichever reader simulates first, no frame that another
+            // reader needs is dropped before it is read
+            let readers: Vec<_> = [2000, 2000, 1500]
+                .into_iter()
+                .map(|frame| {
+                    let track = Arc::clone(&track);
+                    let barrier = Arc::clone(&barrier);
+                    std::thread::spawn(move || {
+                        barrier.wait();
+                        (frame, track.entity_positions_at(frame))
+                    })
+                })
+                .collect();
+            for reader in readers {
+                let (frame, riders) = reader.join().unwrap();
+                assert_eq!(riders, expected.entity_positions_at(frame));
+            }
+
+            assert_eq!(track.simulated_frame_count(), 2000, "{policy:?}");
+        }
+    }
+
+    #[test]
+    fn late_reader_simulates_from_checkpoint() {
+        let track_bytes =
+            fs::read_to_string("./fixtures/testTrack.track.json").expect("Failed to read file");
+        let mut track = read(&track_bytes).expect("Failed to parse file");
+        let expected = read(&track_bytes).expect("Failed to parse file");
+        track.set_cache_policy(CachePolicy::Checkpoints {
+            interval: 100,
+            memory_budget: None,
+            eviction: Eviction::LeastRecentlyUsed,
+        });
+
+        // another thread simulated up to frame 200 and passed frame 150, which is not a
+        // checkpoint, before anyone asked for it
+        {
+            let mut cache = track.position_cache();
+            cache.set_simulating(Some(1..=200));
+            for frame in 1..=200 {
+                cache.simulated(frame, &expected.entity_positions_at(frame), []);
+            }
+            cache.set_simulating(None);
+        }
+
+        assert_eq!(
+            track.entity_positions_at(150),
+            expected.entity_positions_at(150)
+        );
+        assert_eq!(track.simulated_frame_count(), 250);
+    }
+
+    #[test]
+    fn simulation_stays_within_memory_budget() {
+        let track_bytes =
+            fs::read_to_string("./fixtures/testTrack.track.json").expect("Failed to read file");
+        let mut track = read(&track_bytes).expect("Failed to parse file");
+        let expected = read(&track_bytes).expect("Failed to parse file");
+        let frame_size = track.estimated_cache_size();
+        let memory_budget = 10 * frame_size;
+        track.set_cache_policy(CachePolicy::Checkpoints {
+            interval: 10,
+            memory_budget: Some(memory_budget),
+            eviction: Eviction::LeastRecentlyUsed,
+        });
+
+        let mut cache = track.position_cache();
+        cache.set_simulating(Some(1..=2000));
+        cache.await_frame(1500);
+        for frame in 1..=2000 {
+            let awaited = cache.simulated(frame, &expected.entity_positions_at(frame), []);
+            assert_eq!(awaited, frame == 1500);
+
+            // only the frame handed over to the waiting thread is kept beyond the budget
+            let handed_over = if (1500..2000).contains(&frame) {
+                frame_size
+            } else {
+                0
+            };
+            assert!(cache.estimated_size() <= memory_budget + handed_over);
+
+            if frame == 1999 {
+                assert_eq!(
+                    cache.leave_awaited(1500),
+                    Some(expected.entity_positions_at(1500))
+                );
+            }
+        }
+        cache.set_simulating(None);
+    }
+
+    #[test]
+    fn modern_test() {
+        let track_bytes =
+            fs::read_to_string("./fixtures/testTrack.track.json").expect("Failed to read file");
+        let track = read(&track_bytes).expect("Failed to parse file");
+        assert_eq!(track.info.title, "testTrack");
+        assert_eq!(track.all_lines().len(), 150);
+    }
+
+    #[test]
+    fn serde_round_trip() {
+        let track_bytes = fs::read_to_string("./fixtures/legacyTestTrack.track.json")
+            .expect("Failed to read file");
+        let track = read(&track_bytes).expect("Failed to parse file");
+
+        let serialized = serde_json::to_string(&track).expect("Failed to serialize track");
+        let deserialized: Track =
+            serde_json::from_str(&serialized).expect("Failed to deserialize track");
+
+        assert_eq!(track.meta(), deserialized.meta());
+        assert_eq!(track.info, deserialized.info);
+        assert_eq!(track.all_lines(), deserialized.all_lines());
+        assert_eq!(
+            track.entity_positions_at(300),
+            deserialized.entity_positions_at(300)
+        );
+    }
+
+    #[test]
+    fn serde_line_types() {
+        for line_type in [
+            LineType::Normal,
+            LineType::Accelerate { amount: 0.5 },
+            LineType::Scenery { width: 2.5 },
+        ] {
+            let value = serde_json::to_value(line_type).expect("Failed to serialize line type");
+            let deserialized: LineType =
+                serde_json::from_value(value).expect("Failed to deserialize line type");
+            assert_eq!(deserialized, line_type);
+        }
+
+        // scenery lines serialized before they had a width
+        let line: Line = serde_json::from_str(
+            r#"{
+                "id": 1,
+                "ends": [{ "location": [0.0, 0.0] }, { "location": [10.0, 0.0] }],
+                "lineType": "Scenery",
+                "flipped": false
+            }"#,
+        )
+        .expect("Failed to deserialize line");
+        assert_eq!(
+            line.line_type,
+            LineType::Scenery {
+                width: DEFAULT_SCENERY_WIDTH
+            }
+        );
+        assert!(serde_json::from_str::<LineType>(r#""Accelerate""#).is_err());
+    }
+
+    #[test]
+    fn serde_unknown_schema_version() {
+        let track = Track::new(vec![Entity::default_boshsled()], vec![]);
+        let mut value = serde_json::to_value(&track).expect("Failed to serialize track");
+        value["schemaVersion"] = 2.into();
+
+        assert!(serde_json::from_value::<Track>(value).is_err());
+    }
+
+    #[test]
+    fn serde_meta_defaults() {
+        let track = Track::new(vec![Entity::default_boshsled()], vec![]);
+        let mut value = serde_json::to_value(&track).expect("Failed to serialize track");
+        let meta = value["meta"].as_object_mut().unwrap();
+        meta.remove("gravity");
+        meta.remove("iterations");
+
+        let deserialized: Track =
+            serde_json::from_value(value).expect("Failed to deserialize track");
+        assert_eq!(deserialized.meta(), track.meta());
+    }
 }
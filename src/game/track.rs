@@ -1,21 +1,145 @@
 use std::cell::RefCell;
+use std::collections::HashSet;
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
 
-use physics::advance_frame::frame_after;
+use anyhow::{bail, Result};
+use physics::advance_frame::{advance_frame, FrameBuffers};
 
+use crate::game::frame_cache::{CachePolicy, FrameCache};
+use crate::game::info::TrackInfo;
 use crate::game::line::Line;
 use crate::game::vector::Vector2D;
-use crate::linestore::grid::Grid;
+use crate::linestore::grid::{Grid, GridIndex, GridRegion, RegionBuffers, MAX_LINE_CELLS};
 use crate::rider::{Entity, EntityPoint};
 use crate::{physics, LineBuilder, DEBUG_PRINT};
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 
-#[derive(Serialize, Deserialize, Clone, Debug, Copy)]
+/// Version of the schema used when serializing a [`Track`].
+const TRACK_SCHEMA_VERSION: u32 = 1;
+
+/// How many cells around a point are searched for lines to collide with.
+const COLLISION_GRID_RADIUS: u8 = 1;
+
+/// Gravity applied to every rider point each frame in Line Rider.
+pub const DEFAULT_GRAVITY: Vector2D = Vector2D(0.0, 0.175);
+/// Number of times bones and lines are resolved each frame in Line Rider.
+pub const DEFAULT_ITERATIONS: u64 = 6;
+
+/// The version of Line Rider whose physics a track rides with.
+///
+/// Only the grid cells a line is registered in, which decide the lines each point can
+/// collide with, follow the version. Any other collision quirks of 6.0 and 6.1 are not
+/// implemented, and neither version is checked against rides recorded in it, so tracks
+/// saved in them may still ride differently than they did for their authors.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum PhysicsVersion {
+    /// The first Flash release with saves.
+    SixZero,
+    SixOne,
+    /// The last Flash release, which linerider.com and Linerider Advanced also follow.
+    #[default]
+    SixTwo,
+}
+
+impl PhysicsVersion {
+    /// Parses a version tag such as `"6.1"`.
+    pub fn from_tag(tag: &str) -> Option<PhysicsVersion> {
+        match tag {
+            "6.0" => Some(PhysicsVersion::SixZero),
+            "6.1" => Some(PhysicsVersion::SixOne),
+            "6.2" => Some(PhysicsVersion::SixTwo),
+            _ => None,
+        }
+    }
+
+    pub fn tag(&self) -> &'static str {
+        match self {
+            PhysicsVersion::SixZero => "6.0",
+            PhysicsVersion::SixOne => "6.1",
+            PhysicsVersion::SixTwo => "6.2",
+        }
+    }
+}
+
+/// Serialized with camelCase keys, like the documents it is stored in. Metadata written
+/// with snake_case keys is still read.
+#[derive(Serialize, Deserialize, Clone, Debug, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
 pub struct TrackMeta {
-    line_extension_ratio: f64,
-    gravity_well_height: f64,
-    // Suggestion: This should probably be per-rider instead of per-engine
-    remount: bool,
-    cell_size: f64,
+    #[serde(alias = "line_extension_ratio")]
+    pub(crate) line_extension_ratio: f64,
+    #[serde(alias = "gravity_well_height")]
+    pub(crate) gravity_well_height: f64,
+    #[serde(alias = "cell_size")]
+    pub(crate) cell_size: f64,
+    #[serde(default, alias = "physics_version")]
+    pub(crate) physics_version: PhysicsVersion,
+    #[serde(default = "default_gravity")]
+    pub(crate) gravity: Vector2D,
+    #[serde(default = "default_iterations")]
+    pub(crate) iterations: u64,
+    #[serde(default)]
+    pub(crate) frictionless: bool,
+}
+
+impl TrackMeta {
+    /// Checks that the sizes of cells, gravity wells and line extensions are finite, and
+    /// that cells and gravity wells have a size at all. Lines cannot be registered in cells
+    /// without a size.
+    pub fn validate(&self) -> Result<()> {
+        for (name, value) in [
+            ("cell size", self.cell_size),
+            ("gravity well height", self.gravity_well_height),
+        ] {
+            if !(value.is_finite() && value > 0.0) {
+                bail!("{name} must be finite and positive, but is {value}");
+            }
+        }
+        // lines without extensions are valid, so only negative ratios are rejected
+        if !(self.line_extension_ratio.is_finite() && self.line_extension_ratio >= 0.0) {
+            bail!(
+                "line extension ratio must be finite and not negative, but is {}",
+                self.line_extension_ratio
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Checks that `line` can be registered in the grid the way the physics version does.
+    /// Points must be finite, and lines may not pass through more than 65536 cells, as
+    /// registering them would not finish in reasonable time. 6.0 registers lines in every
+    /// cell of their bounding box, which is refused for lines whose box spans too many cells.
+    pub fn validate_line(&self, line: &Line) -> Result<()> {
+        if !line.has_finite_points() {
+            bail!("line {} has a point that is not finite", line.id);
+        }
+        if Grid::exceeds_walk_limit(line, self.cell_size) {
+            bail!(
+                "line {} is too long, as it passes through more than {} cells",
+                line.id,
+                MAX_LINE_CELLS
+            );
+        }
+        if Grid::exceeds_cell_limit(line, self.cell_size, self.physics_version) {
+            bail!(
+                "line {} is too long for {} physics, which would register it in more than {} cells",
+                line.id,
+                self.physics_version.tag(),
+                MAX_LINE_CELLS
+            );
+        }
+
+        Ok(())
+    }
+}
+
+fn default_gravity() -> Vector2D {
+    DEFAULT_GRAVITY
+}
+
+fn default_iterations() -> u64 {
+    DEFAULT_ITERATIONS
 }
 
 impl Default for TrackMeta {
@@ -24,19 +148,27 @@ impl Default for TrackMeta {
             line_extension_ratio: 0.25,
             cell_size: 14.0,
             gravity_well_height: 10.0,
-            remount: false,
+            physics_version: PhysicsVersion::default(),
+            gravity: DEFAULT_GRAVITY,
+            iterations: DEFAULT_ITERATIONS,
+            frictionless: false,
         }
     }
 }
 
-/// A track in linerider.
+/// A track in linerider. Tracks can be shared between threads.
 #[derive(Debug)]
 pub struct Track {
-    pub meta: TrackMeta,
+    pub info: TrackInfo,
+
+    meta: TrackMeta,
 
     grid: Grid,
 
-    precomputed_rider_positions: RefCell<Vec<Vec<Entity>>>,
+    /// Locked only to look up and store frames, never while simulating them.
+    precomputed_rider_positions: Mutex<FrameCache>,
+    /// Notified when a simulation stops or reaches a frame that another thread waits for.
+    frame_simulated: Condvar,
 }
 
 impl Track {
@@ -44,10 +176,19 @@ impl Track {
         let meta: TrackMeta = Default::default();
         Track {
             meta,
+            info: Default::default(),
             grid: Grid::new(lines, meta.cell_size),
-            precomputed_rider_positions: RefCell::new(vec![starting_positions]),
+            precomputed_rider_positions: Mutex::new(FrameCache::new(
+                starting_positions,
+                CachePolicy::default(),
+            )),
+            frame_simulated: Condvar::new(),
         }
     }
+    /// Creates a track without checking its lines or riders. Lines that
+    /// [`TrackMeta::validate_line`] refuses are never collided with and riders that
+    /// [`Entity::validate_layout`] refuses never move, so lines and riders that may be invalid
+    /// should be added with [`Track::add_line`] and [`Track::create_entity`] instead.
     pub fn new_with_meta(
         starting_positions: Vec<Entity>,
         lines: Vec<Line>,
@@ -55,11 +196,148 @@ impl Track {
     ) -> Track {
         Track {
             meta,
-            grid: Grid::new(lines, meta.cell_size),
-            precomputed_rider_positions: RefCell::new(vec![starting_positions]),
+            info: Default::default(),
+            grid: Grid::new_with_version(lines, meta.cell_size, meta.physics_version),
+            precomputed_rider_positions: Mutex::new(FrameCache::new(
+                starting_positions,
+                CachePolicy::default(),
+            )),
+            frame_simulated: Condvar::new(),
         }
     }
 
+    pub fn meta(&self) -> &TrackMeta {
+        &self.meta
+    }
+
+    /// Replaces the metadata of the track. The grid is rebuilt if lines need to be
+    /// registered differently, and frames simulated under the old metadata are discarded.
+    /// Returns an error, leaving the track as it was, if `meta` is not valid or a line could
+    /// not be registered under it.
+    pub fn set_meta(&mut self, meta: TrackMeta) -> Result<()> {
+        meta.validate()?;
+        for line in self.all_lines() {
+            meta.validate_line(line)?;
+        }
+        self.replace_meta(meta);
+
+        Ok(())
+    }
+
+    /// [`Track::set_meta`] for metadata that is known to be valid.
+    fn replace_meta(&mut self, meta: TrackMeta) {
+        let old_meta = std::mem::replace(&mut self.meta, meta);
+        if old_meta == meta {
+            return;
+        }
+
+        if old_meta.line_extension_ratio != meta.line_extension_ratio
+            || old_meta.cell_size != meta.cell_size
+            || old_meta.physics_version != meta.physics_version
+        {
+            let lines = self
+                .all_lines()
+                .iter()
+                .map(|line| {
+                    let mut line = *line;
+                    line.set_extension_ratio(meta.line_extension_ratio);
+                    line
+                })
+                .collect();
+            self.grid = Grid::new_with_version(lines, meta.cell_size, meta.physics_version);
+        }
+        self.position_cache_mut().invalidate_from(1);
+    }
+
+    pub fn physics_version(&self) -> PhysicsVersion {
+        self.meta.physics_version
+    }
+
+    /// Switches the track to the grid and collision rules of another version of Line Rider.
+    /// Returns an error if a line is too long for that version, see
+    /// [`TrackMeta::validate_line`].
+    pub fn set_physics_version(&mut self, physics_version: PhysicsVersion) -> Result<()> {
+        self.set_meta(TrackMeta {
+            physics_version,
+            ..self.meta
+        })
+    }
+
+    /// Gets the gravity applied to riders each frame, before each rider's own scaling.
+    pub fn gravity(&self) -> Vector2D {
+        self.meta.gravity
+    }
+
+    pub fn set_gravity(&mut self, gravity: Vector2D) {
+        self.replace_meta(TrackMeta {
+            gravity,
+            ..self.meta
+        });
+    }
+
+    /// Gets the number of times bones and lines are resolved each frame.
+    pub fn iterations(&self) -> u64 {
+        self.meta.iterations
+    }
+
+    pub fn set_iterations(&mut self, iterations: u64) {
+        self.replace_meta(TrackMeta {
+            iterations,
+            ..self.meta
+        });
+    }
+
+    /// Whether lines ignore the friction of rider points, like LRA-CE's frictionless mode.
+    pub fn frictionless(&self) -> bool {
+        self.meta.frictionless
+    }
+
+    pub fn set_frictionless(&mut self, frictionless: bool) {
+        self.replace_meta(TrackMeta {
+            frictionless,
+            ..self.meta
+        });
+    }
+
+    /// Gets how far past its ends a line catches riders, as a fraction of its length.
+    pub fn line_extension_ratio(&self) -> f64 {
+        self.meta.line_extension_ratio
+    }
+
+    /// Returns an error if the ratio is negative or not finite.
+    pub fn set_line_extension_ratio(&mut self, line_extension_ratio: f64) -> Result<()> {
+        self.set_meta(TrackMeta {
+            line_extension_ratio,
+            ..self.meta
+        })
+    }
+
+    /// Gets how far below a line a point can be and still be pushed back onto it.
+    pub fn gravity_well_height(&self) -> f64 {
+        self.meta.gravity_well_height
+    }
+
+    /// Returns an error if the height is not positive or not finite.
+    pub fn set_gravity_well_height(&mut self, gravity_well_height: f64) -> Result<()> {
+        self.set_meta(TrackMeta {
+            gravity_well_height,
+            ..self.meta
+        })
+    }
+
+    /// Gets the size of the cells that lines are registered in.
+    pub fn cell_size(&self) -> f64 {
+        self.meta.cell_size
+    }
+
+    /// Returns an error if the size is not positive or not finite.
+    pub fn set_cell_size(&mut self, cell_size: f64) -> Result<()> {
+        self.set_meta(TrackMeta {
+            cell_size,
+            ..self.meta
+        })
+    }
+
     pub fn line_builder(&self) -> LineBuilder {
         Line::builder().extension_ratio(self.meta.line_extension_ratio)
     }
@@ -69,21 +347,70 @@ impl Track {
         self.grid.all_lines()
     }
 
-    /// Adds a line to the track.
-    pub fn add_line(&mut self, line: Line) {
+    /// Gets a line by its id.
+    pub fn line_with_id(&self, id: i64) -> Option<&Line> {
+        self.grid.line_with_id(id)
+    }
+
+    /// Gets the line connected to the first point of `line`, if any.
+    pub fn left_line_of(&self, line: &Line) -> Option<&Line> {
+        self.line_with_id(line.left_line?)
+    }
+
+    /// Gets the line that continues `line` from its second point, if any.
+    pub fn right_line_of(&self, line: &Line) -> Option<&Line> {
+        self.line_with_id(line.right_line?)
+    }
+
+    /// Adds a line to the track. Only frames that could have collided with it are discarded.
+    /// Returns an error, leaving the track as it was, if [`TrackMeta::validate_line`] refuses
+    /// the line.
+    pub fn add_line(&mut self, line: Line) -> Result<()> {
+        self.meta.validate_line(&line)?;
+        let cells = self.grid.cells_near_line(&line, COLLISION_GRID_RADIUS);
         self.grid.add_line(line);
-        self.precomputed_rider_positions.borrow_mut().drain(1..);
+        self.position_cache_mut().invalidate_cells(&cells);
+
+        Ok(())
     }
 
-    /// Removes a single line from the track.
+    /// Removes a single line from the track. Only frames that could have collided with it
+    /// are discarded.
     pub fn remove_line(&mut self, line: &Line) {
+        let cells = self.grid.cells_near_line(line, COLLISION_GRID_RADIUS);
         self.grid.remove_line(line);
-        self.precomputed_rider_positions.borrow_mut().drain(1..);
+        self.position_cache_mut().invalidate_cells(&cells);
     }
 
     /// Gets all of the lines near a point.
     pub fn lines_near(&self, point: Vector2D) -> Vec<&Line> {
-        self.grid.lines_near(point, 1)
+        self.grid.lines_near(point, COLLISION_GRID_RADIUS)
+    }
+
+    /// Fetches the lines that collisions anywhere in the rectangle between `p1` and `p2`
+    /// could find, so that a rider's collisions need only one grid query per frame. The
+    /// lines are stored in `buffers`, see [`Broadphase::record_queried_cells`].
+    pub(crate) fn broadphase<'a>(
+        &'a self,
+        p1: Vector2D,
+        p2: Vector2D,
+        buffers: &mut RegionBuffers<'a>,
+    ) -> Broadphase<'a> {
+        Broadphase {
+            track: self,
+            region: self.grid.region(p1, p2, COLLISION_GRID_RADIUS, buffers),
+            outside_cells: Default::default(),
+        }
+    }
+
+    /// A broadphase that fetches nothing up front, for collisions of lone points that
+    /// would not make use of a fetched area.
+    pub(crate) fn direct_broadphase(&self) -> Broadphase<'_> {
+        Broadphase {
+            track: self,
+            region: None,
+            outside_cells: Default::default(),
+        }
     }
 
     /// Gets all of the lines in a rectangle.
@@ -91,41 +418,141 @@ impl Track {
         self.grid.lines_near_box(p1, p2)
     }
 
-    /// Gets the rider positions for a zero-indexed frame.
+    /// Gets the rider positions for a zero-indexed frame. The frame cache is only locked
+    /// to look up and store frames, so threads reading cached frames never wait for one that
+    /// is simulating.
+    ///
+    /// Only one thread simulates at a time. Threads that need a frame that is being
+    /// simulated wait for it, and threads that need a later frame wait for the simulation to
+    /// end and continue from where it stopped. A frame that the simulation passed before a
+    /// thread asked for it is only simulated again if the cache policy did not keep it.
     pub fn entity_positions_at(&self, frame: usize) -> Vec<Entity> {
-        let mut position_cache = self.precomputed_rider_positions.borrow_mut();
-        if let Some(riders) = position_cache.get(frame) {
-            riders.clone()
-        } else {
-            let len = position_cache.len();
-            for i in len..=frame {
-                if DEBUG_PRINT {
-                    println!("Frame {}", i);
+        let (start, mut riders) = {
+            let mut position_cache = self.position_cache();
+            loop {
+                if let Some(riders) = position_cache.get(frame) {
+                    return riders;
+                }
+                match position_cache.simulating() {
+                    None => break,
+                    Some(simulating) if simulating.contains(&frame) => {
+                        position_cache.await_frame(frame);
+                        position_cache = self.wait_for_simulation(position_cache, |cache| {
+                            cache.awaited_ready(frame) || cache.simulating().is_none()
+                        });
+                        if let Some(riders) = position_cache.leave_awaited(frame) {
+                            return riders;
+                        }
+                    }
+                    Some(_) => {
+                        position_cache = self.wait_for_simulation(position_cache, |cache| {
+                            cache.simulating().is_none()
+                        });
+                    }
                 }
-                let next_positions = frame_after(position_cache.last().unwrap(), self);
-                position_cache.push(next_positions);
             }
+            let (start, riders) = position_cache.closest_before(frame);
+            position_cache.set_simulating(Some(start + 1..=frame));
+            (start, riders)
+        };
+        let simulation = Simulation { track: self };
 
-            position_cache.last().unwrap().clone()
+        let mut buffers = FrameBuffers::default();
+        for i in start + 1..=frame {
+            if DEBUG_PRINT {
+                println!("Frame {}", i);
+            }
+            advance_frame(&mut riders, self, &mut buffers);
+            let awaited =
+                self.position_cache()
+                    .simulated(i, &riders, buffers.queried_cells.drain());
+            if awaited {
+                self.frame_simulated.notify_all();
+            }
         }
+        self.position_cache().set_cursor(frame, &riders);
+        drop(simulation);
+
+        riders
     }
 
-    /// Adds a new rider to the track.
-    pub fn create_entity(&mut self, entity: Entity) {
-        let position_cache = self.precomputed_rider_positions.get_mut();
-        let initial_frame = position_cache.get_mut(0).unwrap();
-        initial_frame.push(entity);
+    /// Gets how many frames have been simulated, counting frames that were simulated again
+    /// after edits or after the cache policy dropped them.
+    pub fn simulated_frame_count(&self) -> u64 {
+        self.position_cache().simulated_count()
+    }
 
-        position_cache.drain(1..);
+    /// Gets the number of frames that are stored in the cache, including the starting
+    /// positions at frame 0.
+    pub fn cached_frame_count(&self) -> usize {
+        self.position_cache().len()
+    }
+
+    /// Gets roughly how many bytes the frames kept by the cache take up, to compare with the
+    /// memory budget of [`CachePolicy::Checkpoints`].
+    pub fn estimated_cache_size(&self) -> usize {
+        self.position_cache().estimated_size()
+    }
+
+    pub fn cache_policy(&self) -> CachePolicy {
+        self.position_cache().policy()
+    }
+
+    /// Changes how simulated frames are kept. Every frame but the starting positions is
+    /// discarded.
+    pub fn set_cache_policy(&mut self, policy: CachePolicy) {
+        self.position_cache_mut().set_policy(policy);
+    }
+
+    /// Locks the frame cache. A thread that panicked while simulating leaves only whole
+    /// frames behind, so the cache stays usable.
+    pub(super) fn position_cache(&self) -> MutexGuard<'_, FrameCache> {
+        self.precomputed_rider_positions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Waits on the frame cache until `done` holds.
+    fn wait_for_simulation<'a>(
+        &self,
+        position_cache: MutexGuard<'a, FrameCache>,
+        mut done: impl FnMut(&mut FrameCache) -> bool,
+    ) -> MutexGuard<'a, FrameCache> {
+        self.frame_simulated
+            .wait_while(position_cache, |cache| !done(cache))
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn position_cache_mut(&mut self) -> &mut FrameCache {
+        self.precomputed_rider_positions
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Adds a new rider to the track. Returns an error, leaving the track as it was, if the
+    /// layout of the rider is not valid, see [`Entity::validate_layout`].
+    pub fn create_entity(&mut self, entity: Entity) -> Result<()> {
+        entity.validate_layout()?;
+        let position_cache = self.position_cache_mut();
+        position_cache.starting_positions_mut().push(entity);
+
+        position_cache.invalidate_from(1);
+        Ok(())
+    }
+
+    /// Adds a bosh sled that starts at `position`, see [`Entity::starting_boshsled`].
+    pub fn create_rider(&mut self, position: Vector2D, velocity: Vector2D, rotation: f64) {
+        self.create_entity(Entity::starting_boshsled(position, velocity, rotation))
+            .expect("bosh sleds have every point of their bones and joints");
     }
 
     /// Removes a rider from the track.
     pub fn remove_entity(&mut self, entity: Entity) -> Option<()> {
-        let position_cache = self.precomputed_rider_positions.get_mut();
-        let initial_frame = position_cache.get_mut(0).unwrap();
+        let position_cache = self.position_cache_mut();
+        let initial_frame = position_cache.starting_positions_mut();
         initial_frame.remove(initial_frame.iter().position(|e| *e == entity)?);
 
-        position_cache.drain(1..);
+        position_cache.invalidate_from(1);
         Some(())
     }
 
@@ -182,12 +609,141 @@ impl Track {
     }
 }
 
+/// Ends a simulation started by [`Track::entity_positions_at`] when dropped, even by a
+/// panic, so that threads waiting for it do not wait forever.
+struct Simulation<'a> {
+    track: &'a Track,
+}
+
+impl Drop for Simulation<'_> {
+    fn drop(&mut self) {
+        self.track.position_cache().set_simulating(None);
+        self.track.frame_simulated.notify_all();
+    }
+}
+
+/// The lines near the area that a rider moves through during a frame.
+pub(crate) struct Broadphase<'a> {
+    track: &'a Track,
+    region: Option<GridRegion<'a>>,
+    /// Cells searched around points outside of `region`, which are rare.
+    outside_cells: RefCell<Vec<GridIndex>>,
+}
+
+impl<'a> Broadphase<'a> {
+    /// Gets the same lines as [`Track::lines_near`], in the same order, remembering where
+    /// the frame being simulated searched so that edits only discard the frames they affect.
+    /// Points outside of the fetched area search the grid directly.
+    pub(crate) fn collision_lines_near(
+        &self,
+        point: Vector2D,
+    ) -> impl Iterator<Item = &'a Line> + '_ {
+        let cell = self.track.grid.cell_at(point);
+        let near = self
+            .region
+            .as_ref()
+            .and_then(|region| region.lines_near(cell, COLLISION_GRID_RADIUS));
+        let outside = near.is_none().then(|| {
+            self.outside_cells.borrow_mut().push(cell);
+            self.track.lines_near(point)
+        });
+        near.into_iter()
+            .flatten()
+            .chain(outside.into_iter().flatten())
+    }
+
+    /// Adds the cells that [`Broadphase::collision_lines_near`] may have searched around to
+    /// `cells`: every cell the fetched area was built for, and any searched outside of it.
+    /// The fetched lines go back to `buffers` for the next broadphase.
+    pub(crate) fn record_queried_cells(
+        self,
+        cells: &mut HashSet<GridIndex>,
+        buffers: &mut RegionBuffers<'a>,
+    ) {
+        cells.extend(self.region.iter().flat_map(GridRegion::centers));
+        cells.extend(self.outside_cells.into_inner());
+        if let Some(region) = self.region {
+            *buffers = region.into_buffers();
+        }
+    }
+}
+
 impl Clone for Track {
     fn clone(&self) -> Self {
         Track {
-            meta: self.meta.clone(),
+            meta: self.meta,
+            info: self.info.clone(),
             grid: self.grid.clone(),
-            precomputed_rider_positions: self.precomputed_rider_positions.clone(),
+            precomputed_rider_positions: Mutex::new(self.position_cache().settled_clone()),
+            frame_simulated: Condvar::new(),
         }
     }
 }
+
+/// The serialized form of a [`Track`]: its lines, metadata and the entities at frame 0.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SerializeTrack<'a> {
+    schema_version: u32,
+    meta: &'a TrackMeta,
+    info: &'a TrackInfo,
+    lines: &'a Vec<Line>,
+    entities: Vec<Entity>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DeserializeTrack {
+    schema_version: u32,
+    meta: TrackMeta,
+    #[serde(default)]
+    info: TrackInfo,
+    lines: Vec<Line>,
+    entities: Vec<Entity>,
+}
+
+impl Serialize for Track {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SerializeTrack {
+            schema_version: TRACK_SCHEMA_VERSION,
+            meta: &self.meta,
+            info: &self.info,
+            lines: self.all_lines(),
+            entities: self.entity_positions_at(0),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Track {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let track = DeserializeTrack::deserialize(deserializer)?;
+        if track.schema_version != TRACK_SCHEMA_VERSION {
+            return Err(de::Error::custom(format!(
+                "unsupported track schema version {}",
+                track.schema_version
+            )));
+        }
+        track.meta.validate().map_err(de::Error::custom)?;
+        for line in &track.lines {
+            track.meta.validate_line(line).map_err(de::Error::custom)?;
+        }
+        for entity in &track.entities {
+            entity.validate_layout().map_err(de::Error::custom)?;
+        }
+
+        let lines = track
+            .lines
+            .into_iter()
+            .map(|mut line| {
+                line.set_extension_ratio(track.meta.line_extension_ratio);
+                line
+            })
+            .collect();
+
+        let mut result = Track::new_with_meta(track.entities, lines, track.meta);
+        result.info = track.info;
+
+        Ok(result)
+    }
+}
@@ -61,14 +61,15 @@ impl Vector2D {
         Vector2D(self.1, self.0)
     }
 
-    /// Rotates the vector by some arbitrary number of radians
+    /// Rotates the vector by some arbitrary number of radians. Positive angles rotate
+    /// from the x axis towards the y axis, which is clockwise on screen.
     pub fn rotate_rad(self, radians: f64) -> Vector2D {
         let sin_angle = f64::sin(radians);
         let cos_angle = f64::cos(radians);
 
         Vector2D(
             self.0 * cos_angle - self.1 * sin_angle,
-            self.0 * sin_angle - self.1 * cos_angle,
+            self.0 * sin_angle + self.1 * cos_angle,
         )
     }
 
@@ -1,5 +1,6 @@
 extern crate core;
 
+pub mod formats;
 mod game;
 mod linestore;
 pub mod physics;
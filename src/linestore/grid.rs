@@ -1,23 +1,51 @@
-use std::collections::{BTreeSet, HashMap};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::mem;
+use std::ops::Range;
 
 use crate::game::Line;
+use crate::game::PhysicsVersion;
 use crate::game::Vector2D;
 use crate::linestore::raw_store::{RawStore, RemoveLineResult, StoreIndex};
 
+/// The most cells that a line may be registered in. Registering a line takes a step per
+/// cell, so lines that reach further are refused before they are added. 6.0 registers
+/// lines in their whole bounding box, which grows with the square of a line's length.
+pub(crate) const MAX_LINE_CELLS: u64 = 1 << 16;
+
 /// Data structure used to query lines nearby the rider in
 /// an efficient manner.
 #[derive(PartialEq, Clone, Default, Debug)]
 pub struct Grid {
     lines: RawStore,
     cell_size: f64,
+    version: PhysicsVersion,
+
+    grid: HashMap<GridIndex, Vec<CellEntry>>,
+}
 
-    grid: HashMap<GridIndex, Vec<StoreIndex>>,
+/// A line registered in a cell. Cells are ordered by id and then by slot.
+#[derive(PartialEq, Clone, Copy, Debug)]
+struct CellEntry {
+    id: i64,
+    line: StoreIndex,
+    /// Where the line would be in a list that lines are pushed onto and swap-removed from.
+    /// Lines that share an id are collided with in this order, as they were when cells were
+    /// such lists and were sorted by id for every query.
+    slot: usize,
 }
 
 impl Grid {
     pub fn new(lines: Vec<Line>, cell_size: f64) -> Grid {
-        let mut grid: Grid = Default::default();
-        grid.cell_size = cell_size;
+        Grid::new_with_version(lines, cell_size, PhysicsVersion::default())
+    }
+
+    /// Creates a grid that registers lines in cells the way `version` does.
+    pub fn new_with_version(lines: Vec<Line>, cell_size: f64, version: PhysicsVersion) -> Grid {
+        let mut grid = Grid {
+            cell_size,
+            version,
+            ..Default::default()
+        };
         for line in lines {
             grid.add_line(line);
         }
@@ -29,6 +57,10 @@ impl Grid {
         self.lines.all_lines()
     }
 
+    pub fn line_with_id(&self, id: i64) -> Option<&Line> {
+        self.lines.line_with_id(id)
+    }
+
     pub fn lines_near(&self, loc: Vector2D, grid_radius: u8) -> Vec<&Line> {
         let mut result: Vec<&Line> = vec![];
 
@@ -41,13 +73,7 @@ impl Grid {
                 grid_index.0 += dx;
                 grid_index.1 += dy;
 
-                if let Some(store_indices) = self.grid.get(&grid_index) {
-                    let lines_in_cell: BTreeSet<&Line> = store_indices
-                        .iter()
-                        .map(|line_idx| self.lines.line_at(*line_idx).expect("no line at index"))
-                        .collect();
-                    result.extend(lines_in_cell);
-                }
+                result.extend(self.lines_in_cell(&grid_index));
             }
         }
 
@@ -56,6 +82,89 @@ impl Grid {
         result
     }
 
+    /// Fetches the cells that [`Grid::lines_near`] with `grid_radius` searches from anywhere
+    /// in the rectangle between `loc1` and `loc2`, or returns `None` if the rectangle spans
+    /// too many cells to be worth fetching. The region is built in `buffers`, which
+    /// [`GridRegion::into_buffers`] gives back once it is no longer needed.
+    pub(crate) fn region<'a>(
+        &'a self,
+        loc1: Vector2D,
+        loc2: Vector2D,
+        grid_radius: u8,
+        buffers: &mut RegionBuffers<'a>,
+    ) -> Option<GridRegion<'a>> {
+        let grid_radius = grid_radius as i64;
+        let idx1 = self.cell_at(loc1);
+        let idx2 = self.cell_at(loc2);
+        let min = GridIndex(
+            i64::min(idx1.0, idx2.0).checked_sub(grid_radius)?,
+            i64::min(idx1.1, idx2.1).checked_sub(grid_radius)?,
+        );
+        let max = GridIndex(
+            i64::max(idx1.0, idx2.0).checked_add(grid_radius)?,
+            i64::max(idx1.1, idx2.1).checked_add(grid_radius)?,
+        );
+
+        let width = max.0.checked_sub(min.0)?.checked_add(1)?;
+        let height = max.1.checked_sub(min.1)?.checked_add(1)?;
+        if width.checked_mul(height)? > MAX_REGION_CELLS {
+            return None;
+        }
+
+        let RegionBuffers {
+            mut cells,
+            mut lines,
+        } = mem::take(buffers);
+        cells.clear();
+        lines.clear();
+        let mut region = GridRegion {
+            min,
+            max,
+            grid_radius,
+            cells,
+            lines,
+        };
+        for x in min.0..=max.0 {
+            for y in min.1..=max.1 {
+                let start = region.lines.len();
+                region.lines.extend(self.lines_in_cell(&GridIndex(x, y)));
+                region.cells.push(start..region.lines.len());
+            }
+        }
+
+        Some(region)
+    }
+
+    /// The lines registered in a cell, ordered by id.
+    fn lines_in_cell(&self, index: &GridIndex) -> impl Iterator<Item = &Line> + '_ {
+        self.grid
+            .get(index)
+            .into_iter()
+            .flatten()
+            .map(|entry| self.lines.line_at(entry.line).expect("no line at index"))
+    }
+
+    /// The cell that [`Grid::lines_near`] searches around for `loc`.
+    pub(crate) fn cell_at(&self, loc: Vector2D) -> GridIndex {
+        GridIndex::from_location(loc, self.cell_size)
+    }
+
+    /// The cells that [`Grid::lines_near`] with `grid_radius` can find `line` from.
+    pub(crate) fn cells_near_line(&self, line: &Line, grid_radius: u8) -> HashSet<GridIndex> {
+        let grid_radius = grid_radius as i64;
+
+        let mut cells = HashSet::new();
+        for cell in self.cells_of(line) {
+            for dx in -grid_radius..=grid_radius {
+                for dy in -grid_radius..=grid_radius {
+                    cells.insert(GridIndex(cell.0 + dx, cell.1 + dy));
+                }
+            }
+        }
+
+        cells
+    }
+
     pub fn lines_near_box(&self, loc1: Vector2D, loc2: Vector2D) -> Vec<&Line> {
         self.line_indices_in_rectangle(loc1, loc2)
             .into_iter()
@@ -66,8 +175,17 @@ impl Grid {
     pub fn add_line(&mut self, line: Line) {
         let lines_idx = self.lines.add_line(line);
 
-        for index in GridIndex::iter_over_line(&line, self.cell_size) {
-            self.grid.entry(index).or_default().push(lines_idx);
+        for index in self.cells_of(&line) {
+            // cells are kept ordered by id, so that queries need not sort them. The new slot is
+            // the last one, so the line goes after the lines that share its id.
+            let cell = self.grid.entry(index).or_default();
+            let entry = CellEntry {
+                id: line.id,
+                line: lines_idx,
+                slot: cell.len(),
+            };
+            let position = cell.partition_point(|other| other.id <= line.id);
+            cell.insert(position, entry);
         }
     }
 
@@ -88,11 +206,11 @@ impl Grid {
 
                 // replace instances of line
                 if let Some(line) = self.lines.line_at(to_idx) {
-                    for grid_idx in GridIndex::iter_over_line(line, self.cell_size) {
-                        if let Some(idxs) = self.grid.get_mut(&grid_idx) {
-                            idxs.iter_mut().for_each(|idx| {
-                                if *idx == from_idx {
-                                    *idx = to_idx
+                    for grid_idx in self.cells_of(line) {
+                        if let Some(cell) = self.grid.get_mut(&grid_idx) {
+                            cell.iter_mut().for_each(|entry| {
+                                if entry.line == from_idx {
+                                    entry.line = to_idx
                                 }
                             })
                         }
@@ -103,15 +221,61 @@ impl Grid {
     }
 
     fn remove_line_for_real(&mut self, line: &Line, replaced_idx: StoreIndex) {
-        for grid_idx in GridIndex::iter_over_line(line, self.cell_size) {
-            if let Some(idxs) = self.grid.get_mut(&grid_idx) {
-                if let Some(idx_pos) = idxs.iter().position(|idx| *idx == replaced_idx) {
-                    idxs.swap_remove(idx_pos);
+        for grid_idx in self.cells_of(line) {
+            if let Some(cell) = self.grid.get_mut(&grid_idx) {
+                if let Some(position) = cell.iter().position(|entry| entry.line == replaced_idx) {
+                    let removed = cell.remove(position);
+
+                    // the line in the last slot moves into the freed one, like a swap_remove
+                    let last_slot = cell.len();
+                    if let Some(position) = cell.iter().position(|entry| entry.slot == last_slot) {
+                        let mut moved = cell.remove(position);
+                        moved.slot = removed.slot;
+                        let position = cell.partition_point(|other| {
+                            (other.id, other.slot) < (moved.id, moved.slot)
+                        });
+                        cell.insert(position, moved);
+                    }
                 }
             }
         }
     }
 
+    /// Whether `version` would register `line` in more than [`MAX_LINE_CELLS`] cells.
+    pub(crate) fn exceeds_cell_limit(line: &Line, cell_size: f64, version: PhysicsVersion) -> bool {
+        match version {
+            // a bounding box holds every cell that a walk could enter
+            PhysicsVersion::SixZero => {
+                GridIndex::bounding_box_size(line, cell_size) > MAX_LINE_CELLS
+            }
+            PhysicsVersion::SixOne | PhysicsVersion::SixTwo => {
+                Grid::exceeds_walk_limit(line, cell_size)
+            }
+        }
+    }
+
+    /// Whether walking the cells that `line` passes through, as 6.1 and 6.2 do, would take
+    /// more than [`MAX_LINE_CELLS`] steps.
+    pub(crate) fn exceeds_walk_limit(line: &Line, cell_size: f64) -> bool {
+        GridIndex::walk_length(line, cell_size) > MAX_LINE_CELLS
+    }
+
+    /// The cells a line is registered in. Lines with points that are not finite, or that
+    /// exceed [`MAX_LINE_CELLS`], are registered in none, as listing their cells might not
+    /// end. Tracks refuse such lines before they reach the grid.
+    fn cells_of(&self, line: &Line) -> Vec<GridIndex> {
+        if !line.has_finite_points() || Grid::exceeds_cell_limit(line, self.cell_size, self.version)
+        {
+            return vec![];
+        }
+
+        match self.version {
+            PhysicsVersion::SixZero => GridIndex::bounding_box_of_line(line, self.cell_size),
+            PhysicsVersion::SixOne => GridIndex::step_over_line(line, self.cell_size),
+            PhysicsVersion::SixTwo => GridIndex::iter_over_line(line, self.cell_size).collect(),
+        }
+    }
+
     fn line_indices_in_rectangle(&self, loc1: Vector2D, loc2: Vector2D) -> Vec<StoreIndex> {
         let mut nearby_line_indices: BTreeSet<StoreIndex> = Default::default();
 
@@ -121,9 +285,9 @@ impl Grid {
         for x in i64::min(idx1.0, idx2.0)..=i64::max(idx1.0, idx2.0) {
             for y in i64::min(idx1.1, idx2.1)..=i64::max(idx1.1, idx2.1) {
                 let grid_index = GridIndex(x, y);
-                if let Some(store_indices) = self.grid.get(&grid_index).cloned() {
-                    for store_index in store_indices {
-                        nearby_line_indices.insert(store_index);
+                if let Some(cell) = self.grid.get(&grid_index) {
+                    for entry in cell {
+                        nearby_line_indices.insert(entry.line);
                     }
                 }
             }
@@ -133,8 +297,86 @@ impl Grid {
     }
 }
 
+/// The most cells that [`Grid::region`] fetches at once.
+const MAX_REGION_CELLS: i64 = 1024;
+
+/// The lines of a rectangle of cells, fetched from a [`Grid`] at once.
+#[derive(Debug)]
+pub(crate) struct GridRegion<'a> {
+    min: GridIndex,
+    max: GridIndex,
+    /// How far around a location [`Grid::region`] fetched cells.
+    grid_radius: i64,
+    /// The lines of each cell as a range of `lines`, by column and then by row.
+    cells: Vec<Range<usize>>,
+    lines: Vec<&'a Line>,
+}
+
+/// The storage of a [`GridRegion`], kept between regions so that fetching one does not
+/// allocate once the buffers are large enough.
+#[derive(Default)]
+pub(crate) struct RegionBuffers<'a> {
+    cells: Vec<Range<usize>>,
+    lines: Vec<&'a Line>,
+}
+
+impl<'a> GridRegion<'a> {
+    /// Gives back the buffers the region was built in, for the next one.
+    pub(crate) fn into_buffers(self) -> RegionBuffers<'a> {
+        RegionBuffers {
+            cells: self.cells,
+            lines: self.lines,
+        }
+    }
+
+    /// The cells that searches answered by [`GridRegion::lines_near`] can be centered on,
+    /// which are those of the rectangle the region was fetched for.
+    pub(crate) fn centers(&self) -> impl Iterator<Item = GridIndex> {
+        let (min, max, radius) = (self.min, self.max, self.grid_radius);
+        (min.0 + radius..=max.0 - radius)
+            .flat_map(move |x| (min.1 + radius..=max.1 - radius).map(move |y| GridIndex(x, y)))
+    }
+
+    /// Gets the same lines, in the same order, as [`Grid::lines_near`] does for a location in
+    /// `center`, or `None` if the search reaches outside of the region.
+    pub(crate) fn lines_near(
+        &self,
+        center: GridIndex,
+        grid_radius: u8,
+    ) -> Option<impl Iterator<Item = &'a Line> + '_> {
+        let grid_radius = grid_radius as i64;
+        if center.0.saturating_sub(grid_radius) < self.min.0
+            || center.1.saturating_sub(grid_radius) < self.min.1
+            || self.max.0 < center.0.saturating_add(grid_radius)
+            || self.max.1 < center.1.saturating_add(grid_radius)
+        {
+            return None;
+        }
+
+        let height = self.max.1 - self.min.1 + 1;
+        let mut last: Option<&Line> = None;
+        let lines = (-grid_radius..=grid_radius)
+            .flat_map(move |dx| (-grid_radius..=grid_radius).map(move |dy| (dx, dy)))
+            .flat_map(move |(dx, dy)| {
+                let cell = (center.0 + dx - self.min.0) * height + (center.1 + dy - self.min.1);
+                &self.lines[self.cells[cell as usize].clone()]
+            })
+            .copied()
+            // skips repeated lines like the `dedup` in `Grid::lines_near`
+            .filter(move |line| {
+                if last == Some(*line) {
+                    return false;
+                }
+                last = Some(line);
+                true
+            });
+
+        Some(lines)
+    }
+}
+
 #[derive(Eq, PartialEq, Hash, Copy, Clone, Debug, Default)]
-struct GridIndex(i64, i64);
+pub(crate) struct GridIndex(i64, i64);
 
 impl GridIndex {
     fn from_location(loc: Vector2D, cell_size: f64) -> GridIndex {
@@ -166,6 +408,118 @@ impl GridIndex {
     }
 }
 
+impl GridIndex {
+    /// The number of cells in the bounding box of a line.
+    fn bounding_box_size(line: &Line, cell_size: f64) -> u64 {
+        let start = GridIndex::from_location(line.ends.0.location, cell_size);
+        let end = GridIndex::from_location(line.ends.1.location, cell_size);
+        let width = start.0.abs_diff(end.0).saturating_add(1);
+        let height = start.1.abs_diff(end.1).saturating_add(1);
+        width.saturating_mul(height)
+    }
+
+    /// The most cells that walking from one end of a line to the other can enter.
+    fn walk_length(line: &Line, cell_size: f64) -> u64 {
+        let start = GridIndex::from_location(line.ends.0.location, cell_size);
+        let end = GridIndex::from_location(line.ends.1.location, cell_size);
+        start
+            .0
+            .abs_diff(end.0)
+            .saturating_add(start.1.abs_diff(end.1))
+            .saturating_add(1)
+    }
+
+    /// 6.0 registers a line in every cell of its bounding box. This follows how 6.0 tracks
+    /// are described to ride, but it is not yet checked against a ride in 6.0 itself.
+    fn bounding_box_of_line(line: &Line, cell_size: f64) -> Vec<GridIndex> {
+        let start = GridIndex::from_location(line.ends.0.location, cell_size);
+        let end = GridIndex::from_location(line.ends.1.location, cell_size);
+
+        let mut cells = vec![];
+        for x in i64::min(start.0, end.0)..=i64::max(start.0, end.0) {
+            for y in i64::min(start.1, end.1)..=i64::max(start.1, end.1) {
+                cells.push(GridIndex(x, y));
+            }
+        }
+
+        cells
+    }
+
+    /// 6.1 walks from the first point towards the second, jumping to the next cell border
+    /// each step. Going left or up it jumps one unit past the border, so it skips cells
+    /// that the line only clips, and it stops once it leaves the box between the end cells.
+    fn step_over_line(line: &Line, cell_size: f64) -> Vec<GridIndex> {
+        let start = GridIndex::from_location(line.ends.0.location, cell_size);
+        let end = GridIndex::from_location(line.ends.1.location, cell_size);
+        let vec = line.as_vector2d();
+
+        let mut cells = vec![start];
+        if start == end
+            || (vec.0 == 0.0 && vec.1 == 0.0)
+            || !vec.0.is_finite()
+            || !vec.1.is_finite()
+        {
+            return cells;
+        }
+
+        // the walk only moves towards the end, so it enters at most this many cells
+        let max_cells = GridIndex::walk_length(line, cell_size);
+
+        let in_box = |cell: GridIndex| {
+            i64::min(start.0, end.0) <= cell.0
+                && cell.0 <= i64::max(start.0, end.0)
+                && i64::min(start.1, end.1) <= cell.1
+                && cell.1 <= i64::max(start.1, end.1)
+        };
+
+        let mut position = line.ends.0.location;
+        let mut cell = start;
+        loop {
+            let remainder_x = position.0 - cell.0 as f64 * cell_size;
+            let remainder_y = position.1 - cell.1 as f64 * cell_size;
+            let dx = if vec.0 > 0.0 {
+                cell_size - remainder_x
+            } else {
+                -1.0 - remainder_x
+            };
+            let dy = if vec.1 > 0.0 {
+                cell_size - remainder_y
+            } else {
+                -1.0 - remainder_y
+            };
+
+            let next_position = if vec.0 == 0.0 {
+                Vector2D(position.0, position.1 + dy)
+            } else if vec.1 == 0.0 {
+                Vector2D(position.0 + dx, position.1)
+            } else {
+                let y_step = vec.1 / vec.0 * dx;
+                if y_step.abs() < dy.abs() {
+                    Vector2D(position.0 + dx, position.1 + y_step)
+                } else if y_step.abs() == dy.abs() {
+                    Vector2D(position.0 + dx, position.1 + dy)
+                } else {
+                    Vector2D(position.0 + vec.0 * dy / vec.1, position.1 + dy)
+                }
+            };
+            // rounding can leave the position stuck on a cell border
+            if next_position == position {
+                return cells;
+            }
+            position = next_position;
+
+            let next = GridIndex::from_location(position, cell_size);
+            if !in_box(next) || cells.len() as u64 >= max_cells {
+                return cells;
+            }
+            if next != cell {
+                cells.push(next);
+                cell = next;
+            }
+        }
+    }
+}
+
 /// An iterator which iterates over the grid indices that a line intersects.
 struct GridIndexLineIter {
     current_point: Vector2D,
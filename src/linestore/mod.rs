@@ -6,8 +6,9 @@ mod tests {
     use std::collections::HashSet;
 
     use crate::game::Line;
+    use crate::game::PhysicsVersion;
     use crate::game::Vector2D;
-    use crate::linestore::grid::Grid;
+    use crate::linestore::grid::{Grid, RegionBuffers};
 
     const DEFAULT_CELL_SIZE: f64 = 14.0;
 
@@ -328,6 +329,145 @@ mod tests {
         assert_eq!(near, lines.iter().collect::<Vec<_>>());
     }
 
+    /// Three lines in a row, each connected to the next.
+    fn adjacent_lines() -> [Line; 3] {
+        [
+            Line::builder()
+                .id(1)
+                .right_line(Some(2))
+                .point(0.0, 0.0)
+                .point(10.0, 0.0)
+                .build(),
+            Line::builder()
+                .id(2)
+                .left_line(Some(1))
+                .right_line(Some(3))
+                .point(10.0, 0.0)
+                .point(20.0, 0.0)
+                .build(),
+            Line::builder()
+                .id(3)
+                .left_line(Some(2))
+                .point(20.0, 0.0)
+                .point(30.0, 0.0)
+                .build(),
+        ]
+    }
+
+    #[test]
+    fn duplicate_ids_within_cell() {
+        let a = Line::builder()
+            .id(4)
+            .point(0.0, 5.0)
+            .point(10.0, 5.0)
+            .build();
+        let x = Line::builder()
+            .id(4)
+            .point(0.0, 8.0)
+            .point(10.0, 6.0)
+            .build();
+        let y = Line::builder()
+            .id(4)
+            .point(0.0, 2.0)
+            .point(10.0, 3.0)
+            .build();
+        let other = Line::builder()
+            .id(2)
+            .point(0.0, 9.0)
+            .point(10.0, 9.0)
+            .build();
+
+        // every line that shares an id is kept, in the order it was added
+        let mut grid = Grid::new(vec![a, x, other, y, a], 14.0);
+        assert_eq!(
+            grid.lines_near(Vector2D(5.0, 5.0), 0),
+            vec![&other, &a, &x, &y, &a]
+        );
+        let region = grid
+            .region(
+                Vector2D(5.0, 5.0),
+                Vector2D(5.0, 5.0),
+                0,
+                &mut Default::default(),
+            )
+            .expect("region should be small enough to fetch");
+        assert_eq!(
+            region
+                .lines_near(grid.cell_at(Vector2D(5.0, 5.0)), 0)
+                .expect("location is inside of the region")
+                .collect::<Vec<_>>(),
+            vec![&other, &a, &x, &y, &a]
+        );
+
+        // removing a line moves the last line added into its place
+        grid.remove_line(&a);
+        assert_eq!(
+            grid.lines_near(Vector2D(5.0, 5.0), 0),
+            vec![&other, &a, &x, &y]
+        );
+        grid.remove_line(&a);
+        assert_eq!(grid.lines_near(Vector2D(5.0, 5.0), 0), vec![&other, &y, &x]);
+    }
+
+    #[test]
+    fn adjacency_cleared_on_remove() {
+        let [line1, line2, line3] = adjacent_lines();
+
+        let mut grid = Grid::new(vec![line1, line2, line3], DEFAULT_CELL_SIZE);
+        assert_eq!(grid.line_with_id(2), Some(&line2));
+
+        grid.remove_line(&line2);
+
+        assert_eq!(grid.line_with_id(2), None);
+        assert_eq!(grid.line_with_id(1).unwrap().right_line, None);
+        assert_eq!(grid.line_with_id(3).unwrap().left_line, None);
+        assert_eq!(grid.line_with_id(3), Some(&line3));
+    }
+
+    #[test]
+    fn adjacency_restored_on_re_add() {
+        let [line1, line2, line3] = adjacent_lines();
+
+        let mut grid = Grid::new(vec![line1, line2, line3], DEFAULT_CELL_SIZE);
+        grid.remove_line(&line2);
+        // moves line 3 into the removed slot before line 2 comes back
+        grid.remove_line(&line1);
+        grid.add_line(line2);
+
+        assert_eq!(grid.line_with_id(1), None);
+        assert_eq!(grid.line_with_id(3).unwrap().left_line, Some(2));
+        assert_eq!(grid.line_with_id(2).unwrap().left_line, Some(1));
+
+        grid.add_line(line1);
+        assert_eq!(grid.line_with_id(1).unwrap().right_line, Some(2));
+        assert_eq!(grid.line_with_id(2).unwrap().right_line, Some(3));
+
+        grid.remove_line(&line3);
+        assert_eq!(grid.line_with_id(2).unwrap().right_line, None);
+        assert_eq!(grid.line_with_id(1).unwrap().right_line, Some(2));
+    }
+
+    #[test]
+    fn adjacency_kept_with_duplicate_id() {
+        let [line1, ..] = adjacent_lines();
+        let line2 = Line::builder()
+            .id(2)
+            .point(10.0, 0.0)
+            .point(20.0, 0.0)
+            .build();
+        let line2_copy = Line::builder()
+            .id(2)
+            .point(10.0, 5.0)
+            .point(20.0, 5.0)
+            .build();
+
+        let mut grid = Grid::new(vec![line1, line2, line2_copy], DEFAULT_CELL_SIZE);
+        grid.remove_line(&line2);
+
+        assert_eq!(grid.line_with_id(1).unwrap().right_line, Some(2));
+        assert_eq!(grid.line_with_id(2), Some(&line2_copy));
+    }
+
     #[test]
     fn does_not_infinite_loop_lol() {
         Grid::new(
@@ -339,4 +479,130 @@ mod tests {
             DEFAULT_CELL_SIZE,
         );
     }
+
+    #[test]
+    fn six_one_walk_ends() {
+        let lines = vec![
+            Line::builder()
+                .point(f64::NAN, 0.0)
+                .point(30.0, 30.0)
+                .build(),
+            Line::builder()
+                .point(0.0, 0.0)
+                .point(f64::INFINITY, 30.0)
+                .build(),
+            // steps smaller than the spacing of floats this large do not move the position
+            Line::builder()
+                .point(1.0e17, 0.0)
+                .point(1.0e17 + 64.0, 30.0)
+                .build(),
+        ];
+        for line in lines {
+            Grid::new_with_version(vec![line], DEFAULT_CELL_SIZE, PhysicsVersion::SixOne);
+        }
+    }
+
+    #[test]
+    fn six_zero_long_line() {
+        let long = Line::builder()
+            .point(0.0, 0.0)
+            .point(200000.0, 200000.0)
+            .build();
+        assert!(Grid::exceeds_cell_limit(
+            &long,
+            DEFAULT_CELL_SIZE,
+            PhysicsVersion::SixZero
+        ));
+        assert!(!Grid::exceeds_cell_limit(
+            &long,
+            DEFAULT_CELL_SIZE,
+            PhysicsVersion::SixTwo
+        ));
+
+        // tracks refuse the line, so the grid does not register it in cells of another version
+        let grid = Grid::new_with_version(vec![long], DEFAULT_CELL_SIZE, PhysicsVersion::SixZero);
+        assert!(grid.lines_near(Vector2D(100000.0, 100000.0), 1).is_empty());
+        assert_eq!(grid.all_lines(), &vec![long]);
+    }
+
+    #[test]
+    fn version_cell_registration() {
+        let up_left = Line::builder().point(20.0, 20.0).point(1.0, 13.5).build();
+        let diagonal = Line::builder().point(0.0, 0.0).point(41.0, 41.0).build();
+        let grid_for =
+            |version| Grid::new_with_version(vec![up_left, diagonal], DEFAULT_CELL_SIZE, version);
+
+        // 6.1 steps past the cell that the end of the line is in
+        let end_cell = Vector2D(5.0, 5.0);
+        let six_one = grid_for(PhysicsVersion::SixOne);
+        assert!(!six_one.lines_near(end_cell, 0).contains(&&up_left));
+        assert!(six_one
+            .lines_near(Vector2D(18.0, 18.0), 0)
+            .contains(&&up_left));
+        assert!(six_one
+            .lines_near(Vector2D(10.0, 16.0), 0)
+            .contains(&&up_left));
+        assert!(grid_for(PhysicsVersion::SixTwo)
+            .lines_near(end_cell, 0)
+            .contains(&&up_left));
+
+        // 6.0 registers lines in their whole bounding box
+        let corner_cell = Vector2D(30.0, 5.0);
+        assert_eq!(
+            grid_for(PhysicsVersion::SixZero).lines_near(corner_cell, 0),
+            vec![&diagonal]
+        );
+        assert!(grid_for(PhysicsVersion::SixTwo)
+            .lines_near(corner_cell, 0)
+            .is_empty());
+        assert!(grid_for(PhysicsVersion::SixOne)
+            .lines_near(corner_cell, 0)
+            .is_empty());
+    }
+
+    #[test]
+    fn region_matches_lines_near() {
+        let lines: Vec<Line> = (0..20)
+            .map(|i| {
+                let i = i as f64;
+                Line::builder()
+                    .id(i as i64 % 7)
+                    .point(i * 9.0 - 40.0, i * i - 60.0)
+                    .point(80.0 - i * 6.0, i * 5.0 - 30.0)
+                    .build()
+            })
+            .collect();
+        let grid = Grid::new(lines, DEFAULT_CELL_SIZE);
+
+        // a region is built in the buffers of the one before it
+        let mut buffers = RegionBuffers::default();
+        let previous = grid
+            .region(Vector2D(0.0, 0.0), Vector2D(30.0, 30.0), 1, &mut buffers)
+            .expect("region should be small enough to fetch");
+        buffers = previous.into_buffers();
+        let region = grid
+            .region(
+                Vector2D(-50.0, -65.0),
+                Vector2D(45.0, 70.0),
+                1,
+                &mut buffers,
+            )
+            .expect("region should be small enough to fetch");
+        for x in -12..=12 {
+            for y in -12..=12 {
+                let loc = Vector2D(x as f64 * 4.5, y as f64 * 5.5);
+                let near = region
+                    .lines_near(grid.cell_at(loc), 1)
+                    .expect("location is inside of the region");
+                assert_eq!(near.collect::<Vec<_>>(), grid.lines_near(loc, 1));
+            }
+        }
+
+        assert!(region
+            .lines_near(grid.cell_at(Vector2D(60.0, 0.0)), 1)
+            .is_none());
+        assert!(grid
+            .region(Vector2D(0.0, 0.0), Vector2D(1e4, 1e4), 1, &mut buffers)
+            .is_none());
+    }
 }
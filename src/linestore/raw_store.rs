@@ -9,6 +9,13 @@ pub struct StoreIndex(usize);
 pub struct RawStore {
     lines: Vec<Line>,
     line_to_index: HashMap<Line, Vec<usize>>,
+    id_to_index: HashMap<i64, Vec<usize>>,
+
+    /// The `left_line`/`right_line` each line was added with, parallel to `lines`. These
+    /// stay the same when the neighbors are removed, so they can be reconnected.
+    links: Vec<[Option<i64>; 2]>,
+    /// Maps a line id to the indices of the lines that were added pointing at it.
+    linked_from: HashMap<i64, Vec<usize>>,
 }
 
 impl RawStore {
@@ -21,17 +28,39 @@ impl RawStore {
         self.lines.get(idx.0)
     }
 
-    /// Returns the index of the added line
+    /// Returns a line with the given id, if any.
+    pub fn line_with_id(&self, id: i64) -> Option<&Line> {
+        let idx = self.id_to_index.get(&id)?.first()?;
+        self.lines.get(*idx)
+    }
+
+    /// Returns the index of the added line. Lines that pointed at its id before it was
+    /// removed are connected to it again.
     pub fn add_line(&mut self, line: Line) -> StoreIndex {
+        let reconnect = !self.id_to_index.contains_key(&line.id);
+
         self.lines.push(line);
         let idx = self.lines.len() - 1;
 
         self.line_to_index.entry(line).or_default().push(idx);
+        self.id_to_index.entry(line.id).or_default().push(idx);
+
+        let links = [line.left_line, line.right_line];
+        for id in links.into_iter().flatten() {
+            self.linked_from.entry(id).or_default().push(idx);
+        }
+        self.links.push(links);
+
+        if reconnect {
+            self.set_links_to(line.id, Some(line.id));
+        }
 
         StoreIndex(idx)
     }
 
-    /// Removes a line from the store.
+    /// Removes a line from the store. If no other line shares its id, any
+    /// `left_line`/`right_line` references to it are cleared until a line with its id is
+    /// added again.
     ///
     /// If a swap_remove occurred such that the user of the
     /// RawStore should need to update its indices, it returns those indices.
@@ -47,8 +76,16 @@ impl RawStore {
         if idxs.is_empty() {
             self.line_to_index.remove_entry(line);
         }
+        remove_index(&mut self.id_to_index, line.id, idx);
+        if !self.id_to_index.contains_key(&line.id) {
+            self.set_links_to(line.id, None);
+        }
+        for id in self.links[idx].into_iter().flatten() {
+            remove_index(&mut self.linked_from, id, idx);
+        }
 
         self.lines.swap_remove(idx);
+        self.links.swap_remove(idx);
 
         if self.lines.len() == idx {
             return RemoveLineResult::RemovedNoSwap(StoreIndex(idx));
@@ -56,21 +93,55 @@ impl RawStore {
 
         // since we did a swap_remove, update the line that used to be at lines.len and set it to idx
         let line = self.lines.get(idx).unwrap();
-        self.line_to_index
+        for each_idx in self
+            .line_to_index
             .get_mut(line)
             .unwrap()
             .iter_mut()
-            .for_each(|each_idx| {
+            .chain(self.id_to_index.get_mut(&line.id).unwrap().iter_mut())
+        {
+            if *each_idx == self.lines.len() {
+                *each_idx = idx
+            }
+        }
+        for id in self.links[idx].into_iter().flatten() {
+            for each_idx in self.linked_from.get_mut(&id).unwrap().iter_mut() {
                 if *each_idx == self.lines.len() {
                     *each_idx = idx
                 }
-            });
+            }
+        }
 
         RemoveLineResult::RemovedAndNeedsSwap {
             from: StoreIndex(self.lines.len()),
             to: StoreIndex(idx),
         }
     }
+
+    /// Points the lines that were added pointing at `id` to `target` instead.
+    fn set_links_to(&mut self, id: i64, target: Option<i64>) {
+        for &idx in self.linked_from.get(&id).into_iter().flatten() {
+            let [left, right] = self.links[idx];
+            let line = &mut self.lines[idx];
+            if left == Some(id) {
+                line.left_line = target;
+            }
+            if right == Some(id) {
+                line.right_line = target;
+            }
+        }
+    }
+}
+
+fn remove_index<K: Eq + std::hash::Hash>(map: &mut HashMap<K, Vec<usize>>, key: K, idx: usize) {
+    if let Some(idxs) = map.get_mut(&key) {
+        if let Some(pos) = idxs.iter().position(|each_idx| *each_idx == idx) {
+            idxs.swap_remove(pos);
+        }
+        if idxs.is_empty() {
+            map.remove(&key);
+        }
+    }
 }
 
 pub enum RemoveLineResult {
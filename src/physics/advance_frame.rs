@@ -1,16 +1,51 @@
+use std::collections::HashSet;
+use std::mem;
+
+use crate::linestore::grid::{GridIndex, RegionBuffers};
 use crate::physics::entity_physics::UpdateBonesResult;
 use crate::rider::Entity;
 use crate::Track;
 
+/// Buffers that are reused from frame to frame, so that simulating a frame only allocates
+/// while they grow, for points that leave the area fetched for collisions and for riders
+/// that break apart.
+#[derive(Default)]
+pub(crate) struct FrameBuffers<'a> {
+    /// The cells that collision queries searched around.
+    pub(crate) queried_cells: HashSet<GridIndex>,
+    /// The point slots of each bone of the entity being simulated, see
+    /// [`Entity::bone_slots`].
+    pub(crate) bone_slots: Vec<[usize; 2]>,
+    /// The lines fetched for the collisions of the entity being simulated.
+    pub(crate) region: RegionBuffers<'a>,
+    /// Where the next frame is built, holding the buffer of the frame before last.
+    next: Vec<Entity>,
+}
+
 /// Runs the entire physics engine on a frame to get the next frame.
 pub fn frame_after(riders: &[Entity], track: &Track) -> Vec<Entity> {
+    let mut riders = riders.to_vec();
+    advance_frame(&mut riders, track, &mut FrameBuffers::default());
     riders
-        .iter()
-        .flat_map(|entity| match entity.clone().apply_all_physics_ez(track) {
-            UpdateBonesResult::Same(bosh_sled) => vec![bosh_sled],
+}
+
+/// Replaces `riders` with the frame after them, moving entities rather than cloning them.
+pub(crate) fn advance_frame<'a>(
+    riders: &mut Vec<Entity>,
+    track: &'a Track,
+    buffers: &mut FrameBuffers<'a>,
+) {
+    let mut next = mem::take(&mut buffers.next);
+    for entity in riders.drain(..) {
+        match entity.apply_all_physics_with(track, track.gravity(), track.iterations(), buffers) {
+            UpdateBonesResult::Same(bosh_sled) => next.push(bosh_sled),
             UpdateBonesResult::Broken(bosh, sled) => {
-                vec![bosh, sled]
+                next.push(bosh);
+                next.push(sled);
             }
-        })
-        .collect()
+        }
+    }
+
+    mem::swap(riders, &mut next);
+    buffers.next = next;
 }
@@ -8,24 +8,32 @@ pub fn next_bone_locations(
     entity: &Entity,
     broken: bool,
 ) -> Option<(Vector2D, Vector2D)> {
-    let p1 = entity.point_at(bone.p1);
-    let p2 = entity.point_at(bone.p2);
+    bone_locations(
+        bone,
+        entity.point_at(bone.p1).location,
+        entity.point_at(bone.p2).location,
+        broken,
+    )
+}
 
-    let length = p2.location.distance_squared(p1.location).sqrt();
+/// [`next_bone_locations`] for a bone whose points are at `p1` and `p2`.
+pub(crate) fn bone_locations(
+    bone: &Bone,
+    p1: Vector2D,
+    p2: Vector2D,
+    broken: bool,
+) -> Option<(Vector2D, Vector2D)> {
+    let length = p2.distance_squared(p1).sqrt();
 
     match bone.bone_type {
-        BoneType::Normal => Some(bone_resolve(
-            p1.location,
-            p2.location,
-            get_diff(bone.resting_length, length),
-        )),
+        BoneType::Normal => Some(bone_resolve(p1, p2, get_diff(bone.resting_length, length))),
         BoneType::Repel { length_factor } => {
             if length >= bone.resting_length * length_factor {
-                Some((p1.location, p2.location))
+                Some((p1, p2))
             } else {
                 Some(bone_resolve(
-                    p1.location,
-                    p2.location,
+                    p1,
+                    p2,
                     get_diff(bone.resting_length * length_factor, length),
                 ))
             }
@@ -35,7 +43,7 @@ pub fn next_bone_locations(
             if broken || diff > endurance * bone.resting_length * 0.5 {
                 None
             } else {
-                Some(bone_resolve(p1.location, p2.location, diff))
+                Some(bone_resolve(p1, p2, diff))
             }
         }
     }
@@ -1,29 +1,114 @@
-use crate::game::Track;
 use crate::game::Vector2D;
-use crate::physics::bone_physics::{joint_should_break, next_bone_locations};
-use crate::physics::line_physics::apply_gravity_wells;
-use crate::rider::{Entity, EntityPoint};
+use crate::game::{Broadphase, Track, DEFAULT_GRAVITY, DEFAULT_ITERATIONS};
+use crate::linestore::grid::RegionBuffers;
+use crate::physics::advance_frame::FrameBuffers;
+use crate::physics::bone_physics::{bone_locations, joint_should_break, next_bone_locations};
+use crate::physics::line_physics::apply_gravity_wells_with;
+use crate::rider::{Bone, BoneType, Entity, EntityPoint, MountState};
 use crate::DEBUG_PRINT;
 
+/// Frames a remountable rider spends dismounting before it may remount. The remount
+/// constants are not yet checked against LRA-CE; `fixtures/remount.track.json` rides
+/// through a full dismount and remount to compare them with.
+pub const DISMOUNT_FRAMES: u32 = 30;
+/// Frames a rider spends remounting, with weakened mount bones, before it is mounted again.
+pub const REMOUNT_FRAMES: u32 = 3;
+/// How much more the mount bones may stretch while remounting.
+pub const REMOUNT_ENDURANCE_FACTOR: f64 = 2.0;
+/// How strongly the mount bones pull while remounting.
+pub const REMOUNT_STRENGTH_FACTOR: f64 = 0.5;
+
 pub type PhysicsEntity = Entity;
 
 impl PhysicsEntity {
-    /// Pushes the points of `self` in accordance to gravity well logic.
+    /// Pushes the points of `self` in accordance to gravity well logic. The lines near all
+    /// points are fetched from the grid at once.
     pub fn apply_gravity_wells(&mut self, track: &Track) {
-        self.mutate_points(|p| apply_gravity_wells(p, track))
+        let broadphase = self.broadphase(track, &mut RegionBuffers::default());
+        self.apply_gravity_wells_with(track, &broadphase)
+    }
+
+    fn apply_gravity_wells_with(&mut self, track: &Track, broadphase: &Broadphase) {
+        self.mutate_points(|p| apply_gravity_wells_with(p, track, broadphase))
+    }
+
+    /// Fetches the lines that `self` may collide with this frame: those near the box swept
+    /// by its points since the last frame, grown by the gravity well height.
+    fn broadphase<'a>(&self, track: &'a Track, buffers: &mut RegionBuffers<'a>) -> Broadphase<'a> {
+        let mut min = Vector2D(f64::INFINITY, f64::INFINITY);
+        let mut max = Vector2D(f64::NEG_INFINITY, f64::NEG_INFINITY);
+        for point in self.points.values() {
+            for location in [point.previous_location, point.location] {
+                min = Vector2D(min.0.min(location.0), min.1.min(location.1));
+                max = Vector2D(max.0.max(location.0), max.1.max(location.1));
+            }
+        }
+
+        let well_height = track.gravity_well_height();
+        let margin = Vector2D(well_height, well_height);
+        track.broadphase(min - margin, max + margin, buffers)
     }
 
     /// Applies bone physics to a list of bones. Moves self because
-    /// a BoshSled may break, causing `self` to become unusable.
-    pub fn apply_bones(mut self) -> UpdateBonesResult {
+    /// a BoshSled may break, causing `self` to become unusable. An entity whose layout is
+    /// not valid, see [`Entity::validate_layout`], is left as it is.
+    pub fn apply_bones(self) -> UpdateBonesResult {
+        let mut bone_slots = vec![];
+        if self.bone_slots(&mut bone_slots).is_err() {
+            return UpdateBonesResult::Same(self);
+        }
+        self.apply_bones_with(&bone_slots)
+    }
+
+    /// [`PhysicsEntity::apply_bones`] with the [`Entity::bone_slots`] of `self`.
+    fn apply_bones_with(mut self, bone_slots: &[[usize; 2]]) -> UpdateBonesResult {
+        if self.resolve_bones(bone_slots) {
+            let (bosh, sled) = self.split();
+            UpdateBonesResult::Broken(bosh, sled)
+        } else {
+            UpdateBonesResult::Same(self)
+        }
+    }
+
+    /// Applies bone physics in place and returns whether a mount bone broke. Mount bones
+    /// are skipped while dismounted and weakened while remounting.
+    fn resolve_bones(&mut self, bone_slots: &[[usize; 2]]) -> bool {
         let mut broken = false;
-        for (i, bone) in self.bones.clone().iter().enumerate() {
+        for (i, &[slot1, slot2]) in bone_slots.iter().enumerate() {
             if DEBUG_PRINT {
                 println!("Subiteration {}", i);
             }
-            if let Some((next_p1, next_p2)) = next_bone_locations(&bone, &self, broken) {
-                self.point_at_mut(bone.p1).location = next_p1;
-                self.point_at_mut(bone.p2).location = next_p2;
+
+            let mut bone = self.bones[i];
+            let mut remounting = false;
+            if let BoneType::Mount { endurance } = bone.bone_type {
+                let scale = match self.mount_state {
+                    MountState::Mounted => self.options.mount_endurance_scale,
+                    MountState::Remounting { .. } => {
+                        remounting = true;
+                        self.options.mount_endurance_scale * REMOUNT_ENDURANCE_FACTOR
+                    }
+                    MountState::Dismounting { .. } | MountState::Dismounted => continue,
+                };
+                // invincible riders never fall off, but one that starts off its sled still
+                // goes through dismounting and remounting like any other
+                let endurance = if self.options.invincible {
+                    f64::INFINITY
+                } else {
+                    endurance * scale
+                };
+                bone.bone_type = BoneType::Mount { endurance };
+            }
+
+            let p1 = self.slot_mut(slot1).location;
+            let p2 = self.slot_mut(slot2).location;
+            if let Some((mut next_p1, mut next_p2)) = bone_locations(&bone, p1, p2, broken) {
+                if remounting {
+                    next_p1 = p1 + (next_p1 - p1) * REMOUNT_STRENGTH_FACTOR;
+                    next_p2 = p2 + (next_p2 - p2) * REMOUNT_STRENGTH_FACTOR;
+                }
+                self.slot_mut(slot1).location = next_p1;
+                self.slot_mut(slot2).location = next_p2;
                 if DEBUG_PRINT {
                     print_points(self.clone());
                 }
@@ -35,12 +120,14 @@ impl PhysicsEntity {
             }
         }
 
-        if broken {
-            let (bosh, sled) = self.split();
-            UpdateBonesResult::Broken(bosh, sled)
-        } else {
-            UpdateBonesResult::Same(self)
-        }
+        broken
+    }
+
+    /// The point in a slot from [`Entity::bone_slots`], which checked that it exists.
+    fn slot_mut(&mut self, slot: usize) -> &mut EntityPoint {
+        self.points
+            .at_slot_mut(slot)
+            .expect("bone slots only refer to points of the entity")
     }
 
     /// Performs the logic of stepping the points of the rider to the next frame.
@@ -65,9 +152,9 @@ impl PhysicsEntity {
     }
 
     /// applies joint logic
-    /// does nothing on non-boshsleds
+    /// does nothing on non-boshsleds or invincible riders
     pub fn apply_all_joints(self) -> UpdateBonesResult {
-        if self.joints.iter().any(|j| joint_should_break(j, &self)) {
+        if !self.options.invincible && self.joints.iter().any(|j| joint_should_break(j, &self)) {
             let (bosh, sled) = self.split();
             UpdateBonesResult::Broken(bosh, sled)
         } else {
@@ -75,27 +162,68 @@ impl PhysicsEntity {
         }
     }
 
-    /// Applies all physics steps to the rider in the correct order.
+    /// Applies all physics steps to the rider in the correct order, with Line Rider's
+    /// gravity and iteration count rather than the track's.
     /// Moves `self` because it may become unusable after the sled breaks.
     pub fn apply_all_physics_ez(self, track: &Track) -> UpdateBonesResult {
-        self.apply_all_physics(track, Vector2D(0.0, 0.175), 6)
+        self.apply_all_physics(track, DEFAULT_GRAVITY, DEFAULT_ITERATIONS)
     }
 
-    /// Applies all physics steps to the rider in the correct order.
-    /// Moves `self` because it may become unusable after the sled breaks.
+    /// Applies all physics steps to the rider in the correct order, using the rider's
+    /// [`RiderOptions`](crate::rider::RiderOptions) to scale `gravity`.
+    /// Moves `self` because it may become unusable after the sled breaks. An entity whose
+    /// layout is not valid, see [`Entity::validate_layout`], is left as it is.
     pub fn apply_all_physics(
-        mut self,
+        self,
         track: &Track,
         gravity: Vector2D,
         iterations: u64,
     ) -> UpdateBonesResult {
-        self.next_points(gravity);
+        self.apply_all_physics_with(track, gravity, iterations, &mut FrameBuffers::default())
+    }
+
+    /// [`PhysicsEntity::apply_all_physics`] that reuses `buffers`, and adds the cells its
+    /// collision queries searched around to them.
+    pub(crate) fn apply_all_physics_with<'a>(
+        mut self,
+        track: &'a Track,
+        gravity: Vector2D,
+        iterations: u64,
+        buffers: &mut FrameBuffers<'a>,
+    ) -> UpdateBonesResult {
+        // like a line that cannot be registered is never collided with, an entity that
+        // cannot be simulated stays where it is
+        if self.bone_slots(&mut buffers.bone_slots).is_err() {
+            return UpdateBonesResult::Same(self);
+        }
+        self.next_points(gravity * self.options.gravity_scale);
 
         if DEBUG_PRINT {
             println!("\nIteration {}", 0);
             print_points(self.clone());
         }
 
+        let broadphase = self.broadphase(track, &mut buffers.region);
+        let bone_slots = &buffers.bone_slots;
+        let result = if self.options.remountable && self.is_bosh_sled() {
+            self.apply_remountable_physics(track, &broadphase, bone_slots, iterations)
+        } else {
+            self.apply_split_physics(track, &broadphase, bone_slots, iterations)
+        };
+        broadphase.record_queried_cells(&mut buffers.queried_cells, &mut buffers.region);
+
+        result
+    }
+
+    /// The bone, gravity well and joint steps of [`PhysicsEntity::apply_all_physics`] for a
+    /// rider that splits into a bosh and a sled when bosh falls off.
+    fn apply_split_physics(
+        self,
+        track: &Track,
+        broadphase: &Broadphase,
+        bone_slots: &[[usize; 2]],
+        iterations: u64,
+    ) -> UpdateBonesResult {
         let mut result = UpdateBonesResult::Same(self);
 
         for i in 0..iterations {
@@ -103,7 +231,9 @@ impl PhysicsEntity {
                 println!("\nEnter iteration {}", i + 1);
             }
             result = match result {
-                UpdateBonesResult::Same(same) => same.apply_bones(),
+                UpdateBonesResult::Same(same) => same.apply_bones_with(bone_slots),
+                // only the frame the rider falls off in gets here, as bosh and sled are
+                // separate entities with their own bone slots afterwards
                 UpdateBonesResult::Broken(bosh, sled) => {
                     let bosh = bosh.apply_bones().unwrap_same();
                     let sled = sled.apply_bones().unwrap_same();
@@ -116,14 +246,14 @@ impl PhysicsEntity {
             }
             match &mut result {
                 UpdateBonesResult::Same(same) => {
-                    same.apply_gravity_wells(track);
+                    same.apply_gravity_wells_with(track, broadphase);
                     if DEBUG_PRINT {
                         print_points(same.clone());
                     }
                 }
                 UpdateBonesResult::Broken(bosh, sled) => {
-                    bosh.apply_gravity_wells(track);
-                    sled.apply_gravity_wells(track);
+                    bosh.apply_gravity_wells_with(track, broadphase);
+                    sled.apply_gravity_wells_with(track, broadphase);
                     if DEBUG_PRINT {
                         print_points(sled.clone());
                         print_points(bosh.clone());
@@ -137,8 +267,105 @@ impl PhysicsEntity {
             UpdateBonesResult::Broken(_, _) => result,
         }
     }
+
+    /// The bone, gravity well and joint steps of [`PhysicsEntity::apply_all_physics`] for a
+    /// rider that may remount. Dismounting only disables the mount bones, so bosh and sled
+    /// stay in one entity; they are split only if the sled itself breaks while mounted.
+    /// A dismounted rider skips the joints, as a split bosh and sled have none.
+    fn apply_remountable_physics(
+        mut self,
+        track: &Track,
+        broadphase: &Broadphase,
+        bone_slots: &[[usize; 2]],
+        iterations: u64,
+    ) -> UpdateBonesResult {
+        let mut dismounted = false;
+        for i in 0..iterations {
+            if DEBUG_PRINT {
+                println!("\nEnter iteration {}", i + 1);
+            }
+            if self.resolve_bones(bone_slots) {
+                // the remaining iterations skip the mount bones, as they would after a split
+                dismounted = true;
+                self.mount_state = MountState::Dismounting {
+                    frames_left: DISMOUNT_FRAMES,
+                };
+            }
+            self.apply_gravity_wells_with(track, broadphase);
+            if DEBUG_PRINT {
+                println!("Iteration {}", i + 1);
+                print_points(self.clone());
+            }
+        }
+
+        let joint_breaks = |mount_joint: bool| {
+            !self.options.invincible
+                && self
+                    .joints
+                    .iter()
+                    .filter(|joint| joint.is_mount_joint() == mount_joint)
+                    .any(|joint| joint_should_break(joint, &self))
+        };
+
+        // the sled can break whether or not bosh is on it, but bosh can only fall off once
+        if joint_breaks(false) {
+            let (bosh, sled) = self.split();
+            return UpdateBonesResult::Broken(bosh, sled);
+        }
+        if self.mount_state.is_mounted() {
+            dismounted |= joint_breaks(true);
+        }
+
+        self.mount_state = match self.mount_state {
+            _ if dismounted => MountState::Dismounting {
+                frames_left: DISMOUNT_FRAMES,
+            },
+            MountState::Dismounting { frames_left } if frames_left <= 1 => MountState::Dismounted,
+            MountState::Dismounting { frames_left } => MountState::Dismounting {
+                frames_left: frames_left - 1,
+            },
+            MountState::Dismounted if self.can_remount() => MountState::Remounting {
+                frames_left: REMOUNT_FRAMES,
+            },
+            MountState::Remounting { frames_left } if frames_left <= 1 => MountState::Mounted,
+            MountState::Remounting { frames_left } => MountState::Remounting {
+                frames_left: frames_left - 1,
+            },
+            state => state,
+        };
+
+        UpdateBonesResult::Same(self)
+    }
+
+    /// Whether bosh is close enough to the sled, and in the right pose, to remount.
+    fn can_remount(&self) -> bool {
+        let bones_hold = self.bones.iter().all(|bone| match bone.bone_type {
+            BoneType::Mount { endurance } => {
+                let remount_bone = Bone {
+                    bone_type: BoneType::Mount {
+                        endurance: endurance
+                            * self.options.mount_endurance_scale
+                            * REMOUNT_ENDURANCE_FACTOR,
+                    },
+                    ..*bone
+                };
+                next_bone_locations(&remount_bone, self, false).is_some()
+            }
+            _ => true,
+        });
+
+        bones_hold
+            && !self
+                .joints
+                .iter()
+                .any(|joint| joint.is_mount_joint() && joint_should_break(joint, self))
+    }
 }
 
+// `Broken` is twice the size of `Same`, as entities keep their points inline. Results are
+// matched as soon as they are returned, so the size only costs a copy, while boxing
+// `Broken` would change this public enum.
+#[allow(clippy::large_enum_variant)]
 #[derive(Clone)]
 pub enum UpdateBonesResult {
     Same(PhysicsEntity),
@@ -189,11 +416,7 @@ fn print_points(entity: Entity) {
     );
 }
 
-fn print_point(
-    points: &std::collections::HashMap<crate::rider::PointIndex, crate::rider::EntityPoint>,
-    label: &str,
-    index: crate::rider::PointIndex,
-) {
+fn print_point(points: &crate::rider::EntityPoints, label: &str, index: crate::rider::PointIndex) {
     if let Some(p) = points.get(&index) {
         println!("{}: ({:?})", label, p.location);
     }
@@ -6,13 +6,19 @@ pub mod line_physics;
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
+    use std::fs;
 
+    use crate::formats::trk;
     use crate::game::Line;
     use crate::game::Track;
     use crate::game::Vector2D;
+    use crate::physics::entity_physics::{UpdateBonesResult, DISMOUNT_FRAMES};
     use crate::physics::line_physics::apply_gravity_wells;
-    use crate::rider::{Bone, BoneType, Entity, EntityPoint, PointIndex};
-    use crate::LineType;
+    use crate::rider::{
+        Bone, BoneType, Entity, EntityPoint, EntityPoints, MountState, PointIndex,
+        DEFAULT_START_VELOCITY,
+    };
+    use crate::{LineType, DEFAULT_GRAVITY, DEFAULT_ITERATIONS};
 
     fn _avg_position(entity: &Entity) -> Vector2D {
         let bosh_sum: Vector2D = entity.points.values().map(|p| p.location).sum();
@@ -23,7 +29,7 @@ mod tests {
         let bosh_sum: Vector2D = entity
             .points
             .values()
-            .map(|p| (p.location - p.previous_location))
+            .map(|p| p.location - p.previous_location)
             .sum();
         bosh_sum / entity.points.len() as f64
     }
@@ -31,7 +37,7 @@ mod tests {
     #[test]
     fn update_bones_contract() {
         let bosh = Entity {
-            points: HashMap::from([
+            points: EntityPoints::from([
                 (
                     PointIndex::BoshShoulder,
                     EntityPoint {
@@ -58,13 +64,15 @@ mod tests {
                 bone_type: BoneType::Normal,
             }],
             joints: vec![],
+            options: Default::default(),
+            mount_state: MountState::Mounted,
         };
 
         let bosh = bosh.apply_bones().unwrap_same();
 
         assert_eq!(
             bosh.points,
-            HashMap::from([
+            EntityPoints::from([
                 (
                     PointIndex::BoshShoulder,
                     EntityPoint {
@@ -90,7 +98,7 @@ mod tests {
     #[test]
     fn update_bones_expand() {
         let bosh = Entity {
-            points: HashMap::from([
+            points: EntityPoints::from([
                 (
                     PointIndex::BoshShoulder,
                     EntityPoint {
@@ -117,13 +125,15 @@ mod tests {
                 bone_type: BoneType::Normal,
             }],
             joints: vec![],
+            options: Default::default(),
+            mount_state: MountState::Mounted,
         };
 
         let bosh = bosh.apply_bones().unwrap_same();
 
         assert_eq!(
             bosh.points,
-            HashMap::from([
+            EntityPoints::from([
                 (
                     PointIndex::BoshShoulder,
                     EntityPoint {
@@ -174,6 +184,125 @@ mod tests {
         );
     }
 
+    #[test]
+    fn entity_points() {
+        let bosh_sled = Entity::default_boshsled();
+        assert_eq!(bosh_sled.points.len(), 10);
+        assert!(bosh_sled.points.keys().copied().eq(PointIndex::ALL));
+
+        let (bosh, sled) = bosh_sled.clone().split();
+        assert!(bosh.is_bosh() && bosh.points.len() == 6);
+        assert!(sled.is_sled() && sled.points.len() == 4);
+        assert_eq!(
+            bosh.points
+                .into_iter()
+                .chain(sled.points)
+                .collect::<EntityPoints>(),
+            bosh_sled.points
+        );
+
+        // points still serialize as a map from point index to point
+        let json = serde_json::to_value(sled.points).unwrap();
+        assert_eq!(
+            json["SledPeg"],
+            serde_json::to_value(sled.point_at(PointIndex::SledPeg)).unwrap()
+        );
+        assert_eq!(
+            serde_json::from_value::<HashMap<PointIndex, EntityPoint>>(json.clone()).unwrap(),
+            sled.points.into_iter().collect()
+        );
+        assert_eq!(
+            serde_json::from_value::<EntityPoints>(json).unwrap(),
+            sled.points
+        );
+
+        // code written against the old map keeps working through a conversion
+        let mut map = HashMap::from(bosh_sled.points);
+        assert_eq!(map.len(), 10);
+        assert_eq!(
+            map[&PointIndex::BoshButt],
+            *bosh_sled.point_at(PointIndex::BoshButt)
+        );
+        map.remove(&PointIndex::SledRope);
+        let mut points = EntityPoints::from(map);
+        assert!(!points.contains_key(&PointIndex::SledRope));
+        for (index, point) in points.iter_mut() {
+            point.friction = index.slot() as f64;
+        }
+        assert_eq!(points[&PointIndex::SledNose].friction, 8.0);
+    }
+
+    #[test]
+    fn invalid_layout() {
+        let mut ropeless = Entity::default_boshsled();
+        ropeless.points.remove(&PointIndex::SledRope);
+        assert!(ropeless.validate_layout().is_err());
+        assert!(Entity::default_boshsled().validate_layout().is_ok());
+
+        let mut track = Track::new(vec![], vec![]);
+        assert!(track.create_entity(ropeless.clone()).is_err());
+        assert!(track.entity_positions_at(0).is_empty());
+
+        // an entity that cannot be simulated stays where it is instead of panicking
+        match ropeless.clone().apply_bones() {
+            UpdateBonesResult::Same(same) => assert_eq!(same, ropeless),
+            UpdateBonesResult::Broken(_, _) => panic!("invalid entity should not break"),
+        }
+        let track = Track::new(vec![ropeless.clone()], vec![]);
+        assert_eq!(track.entity_positions_at(10), vec![ropeless]);
+
+        let json = serde_json::to_string(&track).unwrap();
+        assert!(serde_json::from_str::<Track>(&json).is_err());
+    }
+
+    #[test]
+    fn starting_boshsled() {
+        assert_eq!(
+            Entity::starting_boshsled(Vector2D(0.0, 0.0), DEFAULT_START_VELOCITY, 0.0),
+            Entity::default_boshsled()
+        );
+
+        let still = Entity::starting_boshsled(Vector2D(3.0, -4.0), Vector2D(0.0, 0.0), 0.0);
+        assert!(still
+            .points
+            .values()
+            .all(|p| p.previous_location == p.location));
+        assert_eq!(
+            still.point_at(PointIndex::SledNose).location,
+            Vector2D(18.0, 1.0)
+        );
+
+        // a rider standing on its tail, rotated around the peg
+        let upright = Entity::starting_boshsled(
+            Vector2D(10.0, 0.0),
+            Vector2D(0.0, -1.0),
+            -std::f64::consts::FRAC_PI_2,
+        );
+        assert_eq!(
+            upright.point_at(PointIndex::SledPeg).location,
+            Vector2D(10.0, 0.0)
+        );
+        let nose = upright.point_at(PointIndex::SledNose);
+        assert!(nose.location.distance_squared(Vector2D(15.0, -15.0)) < 1e-20);
+        assert!(
+            (nose.location - nose.previous_location).distance_squared(Vector2D(0.0, -1.0)) < 1e-20
+        );
+        assert_eq!(upright.bones, Entity::default_boshsled().bones);
+
+        // the bones are already at rest, so the rider keeps its shape
+        let track = Track::new(vec![upright], vec![]);
+        let entities = track.entity_positions_at(10);
+        assert_eq!(entities.len(), 1);
+        for bone in &entities[0].bones {
+            let length = entities[0]
+                .point_at(bone.p1)
+                .location
+                .distance_squared(entities[0].point_at(bone.p2).location)
+                .sqrt();
+            assert!((length - bone.resting_length).abs() < 1e-9);
+        }
+    }
+
     #[test]
     fn rider_physics_bosh_falling() {
         let bosh_sled = Entity::default_boshsled();
@@ -217,7 +346,7 @@ mod tests {
         );
 
         let entities = track.entity_positions_at(10);
-        let entity = entities.get(0).unwrap();
+        let entity = entities.first().unwrap();
 
         // comparison is to linerider.com's physics
         assert_eq!(
@@ -237,14 +366,14 @@ mod tests {
         let track = Track::new(
             vec![entity],
             vec![Line::builder()
-                .line_type(LineType::Accelerate { amount: 1 })
+                .line_type(LineType::Accelerate { amount: 1.0 })
                 .point(-5.0, 1.0)
                 .point(10.0, 1.0)
                 .build()],
         );
 
         let entities = track.entity_positions_at(10);
-        let entity = entities.get(0).unwrap();
+        let entity = entities.first().unwrap();
 
         // comparison is to linerider.com's physics
         assert_eq!(
@@ -257,4 +386,330 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn rider_accel_fractional() {
+        let track_with = |line_type| {
+            Track::new(
+                vec![Entity::default_boshsled()],
+                vec![Line::builder()
+                    .line_type(line_type)
+                    .point(-5.0, 1.0)
+                    .point(10.0, 1.0)
+                    .build()],
+            )
+        };
+        let speed = |line_type| avg_velocity(&track_with(line_type).entity_positions_at(10)[0]).0;
+
+        // a zero multiplier rides exactly like a normal line
+        assert_eq!(
+            track_with(LineType::Accelerate { amount: 0.0 }).entity_positions_at(10),
+            track_with(LineType::Normal).entity_positions_at(10)
+        );
+
+        // integer multipliers still ride the same as before they became f64
+        assert_eq!(
+            track_with(LineType::Accelerate { amount: 1.0 }).entity_positions_at(10)[0]
+                .point_at(PointIndex::SledTail)
+                .location,
+            Vector2D(10.437748868700394, -17.70589979578289)
+        );
+
+        let half = speed(LineType::Accelerate { amount: 0.5 });
+        assert!(speed(LineType::Normal) < half);
+        assert!(half < speed(LineType::Accelerate { amount: 1.0 }));
+        let two_and_a_half = speed(LineType::Accelerate { amount: 2.5 });
+        assert!(speed(LineType::Accelerate { amount: 2.0 }) < two_and_a_half);
+        assert!(two_and_a_half < speed(LineType::Accelerate { amount: 3.0 }));
+    }
+
+    /// Rides `fixtures/frictionless.trk`, which declares the `FRICTIONLESS` feature, so that
+    /// the ride can be compared with LRA-CE. Frames exported from LRA-CE for this fixture are
+    /// still to be added; until then the test compares it with the same track with friction.
+    #[test]
+    fn rider_frictionless() {
+        let bytes = fs::read("./fixtures/frictionless.trk").expect("Failed to read file");
+        let file = trk::read(&bytes).expect("Failed to parse trk");
+        assert!(file.features.frictionless);
+        let mut track = file.track;
+        assert!(track.frictionless());
+        let frictionless = track.entity_positions_at(150);
+
+        track.set_frictionless(false);
+        let with_friction = track.entity_positions_at(150);
+        assert_ne!(frictionless, with_friction);
+        track.set_frictionless(true);
+
+        // frictionless mode rides exactly like riders whose points have no friction
+        let mut slippery_track = remount_track(false);
+        let mut slippery = Entity::default_boshsled();
+        slippery.mutate_points(|p| p.friction = 0.0);
+        slippery_track.remove_entity(Entity::default_boshsled());
+        slippery_track.create_entity(slippery).unwrap();
+
+        let locations = |entities: Vec<Entity>| -> HashMap<_, _> {
+            entities
+                .into_iter()
+                .flat_map(|entity| entity.points)
+                .map(|(index, point)| (index, (point.location, point.previous_location)))
+                .collect()
+        };
+        for frame in 0..=150 {
+            assert_eq!(
+                locations(track.entity_positions_at(frame)),
+                locations(slippery_track.entity_positions_at(frame)),
+                "frame {frame}"
+            );
+        }
+
+        // the bosh slides further along the line without friction
+        let butt_x = |entities: &[Entity]| entities[0].point_at(PointIndex::BoshButt).location.0;
+        assert!(butt_x(&frictionless) > butt_x(&with_friction));
+    }
+
+    fn remount_track(remount: bool) -> Track {
+        let mut rider = Entity::default_boshsled();
+        rider.options.remountable = remount;
+        Track::new(
+            vec![rider],
+            vec![
+                Line::builder()
+                    .point(-20.0, 20.0)
+                    .point(200.0, 120.0)
+                    .build(),
+                Line::builder()
+                    .point(200.0, 120.0)
+                    .point(220.0, 100.0)
+                    .build(),
+                Line::builder()
+                    .point(220.0, 100.0)
+                    .point(800.0, 130.0)
+                    .build(),
+            ],
+        )
+    }
+
+    #[test]
+    fn rider_remount_disabled() {
+        let track = remount_track(false);
+
+        assert_eq!(track.entity_positions_at(72).len(), 1);
+        assert_eq!(track.entity_positions_at(73).len(), 2);
+        assert_eq!(track.entity_positions_at(300).len(), 2);
+    }
+
+    #[test]
+    fn rider_remount() {
+        let track = remount_track(true);
+        let split_track = remount_track(false);
+
+        let state_at = |frame| track.entity_positions_at(frame)[0].mount_state;
+        assert_eq!(state_at(72), MountState::Mounted);
+        assert_eq!(state_at(73), MountState::Dismounting { frames_left: 30 });
+        assert_eq!(state_at(102), MountState::Dismounting { frames_left: 1 });
+        assert_eq!(state_at(103), MountState::Dismounted);
+        assert_eq!(state_at(157), MountState::Dismounted);
+        assert_eq!(state_at(158), MountState::Remounting { frames_left: 3 });
+        assert_eq!(state_at(161), MountState::Mounted);
+
+        // until it remounts, the rider moves exactly like a bosh and sled that split
+        for frame in 0..158 {
+            let entities = track.entity_positions_at(frame);
+            assert_eq!(entities.len(), 1);
+
+            let split_points: EntityPoints = split_track
+                .entity_positions_at(frame)
+                .into_iter()
+                .flat_map(|entity| entity.points)
+                .collect();
+            assert_eq!(entities[0].points, split_points, "frame {frame}");
+        }
+
+        let entities = track.entity_positions_at(300);
+        assert_eq!(entities.len(), 1);
+        assert_eq!(entities[0].mount_state, MountState::Mounted);
+        assert_eq!(entities[0].bones, Entity::default_boshsled().bones);
+        assert_eq!(entities[0].joints, Entity::default_boshsled().joints);
+    }
+
+    #[test]
+    fn rider_remount_bone_break() {
+        // crashing into a slanted wall breaks the mount bones partway through a frame
+        let wall = vec![
+            Line::builder().point(40.0, 20.0).point(0.0, -40.0).build(),
+            Line::builder().point(-20.0, 10.0).point(40.0, 10.0).build(),
+        ];
+        let mut rider = Entity::starting_boshsled(Vector2D(0.0, 0.0), Vector2D(6.0, 0.0), 0.0);
+        let split_track = Track::new(vec![rider.clone()], wall.clone());
+        rider.options.remountable = true;
+        let track = Track::new(vec![rider], wall);
+
+        let first_split = (0..30)
+            .find(|frame| split_track.entity_positions_at(*frame).len() == 2)
+            .expect("rider should fall off");
+        assert_eq!(
+            track.entity_positions_at(first_split)[0].mount_state,
+            MountState::Dismounting {
+                frames_left: DISMOUNT_FRAMES
+            }
+        );
+        for frame in 0..first_split + DISMOUNT_FRAMES as usize {
+            let split_points: EntityPoints = split_track
+                .entity_positions_at(frame)
+                .into_iter()
+                .flat_map(|entity| entity.points)
+                .collect();
+            assert_eq!(
+                track.entity_positions_at(frame)[0].points,
+                split_points,
+                "frame {frame}"
+            );
+        }
+    }
+
+    #[test]
+    fn dismounted_sled_breaks() {
+        let track = Track::new(vec![], vec![]);
+        for mount_state in [
+            MountState::Dismounting {
+                frames_left: DISMOUNT_FRAMES,
+            },
+            MountState::Dismounted,
+        ] {
+            // a sled turned upside down breaks its joint
+            let mut rider = Entity::starting_boshsled(Vector2D(0.0, 0.0), Vector2D(0.0, 0.0), 0.0);
+            rider.options.remountable = true;
+            rider.mount_state = mount_state;
+            for index in [PointIndex::SledNose, PointIndex::SledTail] {
+                let point = rider.point_at_mut(index);
+                point.location.1 = -point.location.1;
+                point.previous_location = point.location;
+            }
+
+            let result = rider.apply_all_physics(&track, DEFAULT_GRAVITY, DEFAULT_ITERATIONS);
+            assert!(
+                matches!(result, UpdateBonesResult::Broken(..)),
+                "{mount_state:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn rider_invincible() {
+        for remountable in [false, true] {
+            let mut track = remount_track(remountable);
+            let mut rider = Entity::default_boshsled();
+            rider.options.remountable = remountable;
+            rider.options.invincible = true;
+            track.remove_entity(track.entity_positions_at(0)[0].clone());
+            track.create_entity(rider).unwrap();
+
+            for frame in [73, 158, 300] {
+                let entities = track.entity_positions_at(frame);
+                assert_eq!(entities.len(), 1);
+                assert!(entities[0].is_bosh_sled());
+                assert_eq!(entities[0].mount_state, MountState::Mounted);
+            }
+        }
+
+        // a normal rider falls off the sled when it crashes into a wall
+        let wall = vec![Line::builder().point(40.0, 20.0).point(40.0, -40.0).build()];
+        let mut rider = Entity::starting_boshsled(Vector2D(0.0, 0.0), Vector2D(7.0, 0.0), 0.0);
+        assert_eq!(
+            Track::new(vec![rider.clone()], wall.clone())
+                .entity_positions_at(30)
+                .len(),
+            2
+        );
+        rider.options.invincible = true;
+        assert_eq!(
+            Track::new(vec![rider], wall).entity_positions_at(30).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn invincible_rider_dismounted() {
+        let dismounted_rider = |invincible: bool| {
+            let mut rider = Entity::default_boshsled();
+            rider.options.remountable = true;
+            rider.options.invincible = invincible;
+            rider.mount_state = MountState::Dismounting {
+                frames_left: DISMOUNT_FRAMES,
+            };
+            rider.points.retain(|index, point| {
+                if index.is_bosh() {
+                    point.location += Vector2D(0.0, -30.0);
+                    point.previous_location += Vector2D(0.0, -30.0);
+                }
+                true
+            });
+            rider
+        };
+
+        // mount bones stay off while dismounted, however strong they are
+        let track = Track::new(vec![dismounted_rider(false)], vec![]);
+        let invincible_track = Track::new(vec![dismounted_rider(true)], vec![]);
+        for frame in 0..=40 {
+            let entities = track.entity_positions_at(frame);
+            let invincible_entities = invincible_track.entity_positions_at(frame);
+            assert_eq!(
+                entities[0].points, invincible_entities[0].points,
+                "frame {frame}"
+            );
+            assert_eq!(
+                entities[0].mount_state, invincible_entities[0].mount_state,
+                "frame {frame}"
+            );
+        }
+    }
+
+    #[test]
+    fn rider_options_per_rider() {
+        let mut light = Entity::default_boshsled();
+        light.options.gravity_scale = 0.5;
+        let mut sturdy = Entity::default_boshsled();
+        sturdy.options.mount_endurance_scale = 200.0;
+
+        let mut track = remount_track(false);
+        track.create_entity(light.clone()).unwrap();
+        track.create_entity(sturdy).unwrap();
+        let entities = track.entity_positions_at(73);
+
+        // the default rider dismounts while the sturdy one stays on
+        assert_eq!(entities.len(), 4);
+        assert!(entities[..2].iter().all(|e| !e.is_bosh_sled()));
+        assert!(entities[3].is_bosh_sled());
+        assert_eq!(entities[3].options.mount_endurance_scale, 200.0);
+
+        // the scale multiplies the endurance stored on the bones rather than replacing it
+        let mut sturdy_bones = Entity::default_boshsled();
+        for bone in &mut sturdy_bones.bones {
+            if let BoneType::Mount { endurance } = &mut bone.bone_type {
+                *endurance *= 200.0;
+            }
+        }
+        let mut sturdy_alone = remount_track(false);
+        sturdy_alone.remove_entity(Entity::default_boshsled());
+        sturdy_alone.create_entity(sturdy_bones).unwrap();
+        assert_eq!(
+            sturdy_alone.entity_positions_at(73)[0].points,
+            entities[3].points
+        );
+
+        // each rider moves as if it were alone on the track
+        let mut light_alone = remount_track(false);
+        light_alone.remove_entity(Entity::default_boshsled());
+        light_alone.create_entity(light.clone()).unwrap();
+        assert_eq!(entities[2], light_alone.entity_positions_at(73)[0]);
+
+        let peg_fall = |entity: Entity| {
+            let track = Track::new(vec![entity], vec![]);
+            track.entity_positions_at(20)[0]
+                .point_at(PointIndex::SledPeg)
+                .location
+                .1
+        };
+        assert!((peg_fall(light) * 2.0 - peg_fall(Entity::default_boshsled())).abs() < 1e-9);
+    }
 }
@@ -39,3 +39,11 @@ pub struct Joint {
     pub pair1: (PointIndex, PointIndex),
     pub pair2: (PointIndex, PointIndex),
 }
+
+impl Joint {
+    /// Whether the joint connects the bosh to the sled, rather than holding the sled together.
+    pub fn is_mount_joint(&self) -> bool {
+        let points = [self.pair1.0, self.pair1.1, self.pair2.0, self.pair2.1];
+        points.iter().any(|p| p.is_bosh()) && points.iter().any(|p| !p.is_bosh())
+    }
+}
@@ -1,18 +1,47 @@
-use std::collections::HashMap;
-
+use anyhow::{bail, Result};
 use serde::{Deserialize, Serialize};
 
 use crate::game::Vector2D;
 use crate::rider::bone::{Bone, BoneType};
-use crate::rider::point::{EntityPoint, PointIndex};
-use crate::rider::Joint;
+use crate::rider::point::{EntityPoint, EntityPoints, PointIndex};
+use crate::rider::{Joint, RiderOptions, DEFAULT_MOUNT_ENDURANCE};
+
+/// The velocity riders start with in Line Rider.
+pub const DEFAULT_START_VELOCITY: Vector2D = Vector2D(0.4, 0.0);
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Entity {
-    pub points: HashMap<PointIndex, EntityPoint>,
+    pub points: EntityPoints,
 
     pub bones: Vec<Bone>,
     pub joints: Vec<Joint>,
+
+    #[serde(default)]
+    pub options: RiderOptions,
+    /// Where the rider is in the dismount and remount cycle. Only used by remountable
+    /// riders, where a dismounted bosh and sled stay in one entity.
+    #[serde(default)]
+    pub mount_state: MountState,
+}
+
+/// The mount phase of a remountable rider.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MountState {
+    /// Bosh is on the sled and the mount bones are active.
+    #[default]
+    Mounted,
+    /// Bosh fell off recently and cannot remount until the countdown ends.
+    Dismounting { frames_left: u32 },
+    /// Bosh is off the sled and remounts once they line up again.
+    Dismounted,
+    /// The mount bones are active again, but weaker, until the countdown ends.
+    Remounting { frames_left: u32 },
+}
+
+impl MountState {
+    pub fn is_mounted(&self) -> bool {
+        matches!(self, MountState::Mounted | MountState::Remounting { .. })
+    }
 }
 
 impl Entity {
@@ -32,6 +61,29 @@ impl Entity {
             .unwrap_or_else(|| panic!("invalid index {index:?}"))
     }
 
+    /// Returns an error if a bone or joint refers to a point the entity does not have, which
+    /// simulating it would need.
+    pub fn validate_layout(&self) -> Result<()> {
+        check_layout(&self.bones, &self.joints, |index| {
+            self.points.contains_key(&index)
+        })
+    }
+
+    /// Replaces `slots` with the point slots of each bone, in the order of `bones`, so that
+    /// resolving a bone is two array accesses. Returns an error if the layout is not valid,
+    /// see [`Entity::validate_layout`].
+    pub(crate) fn bone_slots(&self, slots: &mut Vec<[usize; 2]>) -> Result<()> {
+        self.validate_layout()?;
+        slots.clear();
+        slots.extend(
+            self.bones
+                .iter()
+                .map(|bone| [bone.p1.slot(), bone.p2.slot()]),
+        );
+
+        Ok(())
+    }
+
     /// Utility function for applying a mapping to all points of the entity
     pub fn mutate_points<F: FnMut(&mut EntityPoint)>(&mut self, mapper: F) {
         self.points.values_mut().for_each(mapper);
@@ -45,9 +97,28 @@ impl Entity {
             points,
             bones,
             joints,
+            options: Default::default(),
+            mount_state: MountState::Mounted,
         }
     }
 
+    /// Creates a bosh sled ready to start riding, with its sled peg at `position`.
+    ///
+    /// Every point starts moving at `velocity`, which is [`DEFAULT_START_VELOCITY`] in Line
+    /// Rider and zero for tracks that start at rest. The rider is rotated by `rotation`
+    /// radians around the sled peg, clockwise on screen.
+    pub fn starting_boshsled(position: Vector2D, velocity: Vector2D, rotation: f64) -> Entity {
+        let mut entity = Entity::default_boshsled();
+        entity.mutate_points(|p| {
+            let location = p.location.rotate_rad(rotation);
+            p.location = location + position;
+            p.previous_location = location - velocity + position;
+            p.momentum = velocity;
+        });
+
+        entity
+    }
+
     pub fn default_bosh() -> Entity {
         let points = bosh::default_points();
         let bones = bosh::default_bones(&points);
@@ -55,6 +126,8 @@ impl Entity {
             points,
             bones,
             joints: Default::default(),
+            options: Default::default(),
+            mount_state: MountState::Mounted,
         }
     }
 
@@ -65,6 +138,8 @@ impl Entity {
             points,
             bones,
             joints: Default::default(),
+            options: Default::default(),
+            mount_state: MountState::Mounted,
         }
     }
 
@@ -82,10 +157,10 @@ impl Entity {
 
     /// Splits a boshsled into a bosh and a sled
     pub fn split(self) -> (Entity, Entity) {
-        let (bosh_points, sled_points) = self
-            .points
-            .into_iter()
-            .partition(|(index, _)| index.is_bosh());
+        let mut bosh_points = self.points;
+        bosh_points.retain(|index, _| index.is_bosh());
+        let mut sled_points = self.points;
+        sled_points.retain(|index, _| !index.is_bosh());
 
         let (bosh_bones, sled_bones) = self
             .bones
@@ -99,11 +174,15 @@ impl Entity {
                 points: bosh_points,
                 bones: bosh_bones,
                 joints: vec![],
+                options: self.options,
+                mount_state: MountState::Mounted,
             },
             Entity {
                 points: sled_points,
                 bones: sled_bones,
                 joints: vec![],
+                options: self.options,
+                mount_state: MountState::Mounted,
             },
         )
     }
@@ -119,20 +198,20 @@ pub mod boshsled {
         + DEFAULT_SLED_MOUNT_BONES
         + DEFAULT_BOSH_MOUNT_BONES;
 
-    pub fn default_points() -> HashMap<PointIndex, EntityPoint> {
+    pub fn default_points() -> EntityPoints {
         sled::default_points()
             .into_iter()
             .chain(bosh::default_points())
             .collect()
     }
 
-    pub fn default_bones(points: &HashMap<PointIndex, EntityPoint>) -> Vec<Bone> {
+    pub fn default_bones(points: &EntityPoints) -> Vec<Bone> {
         // insert mounter bones in between regular and repel bones
         let (bosh_normal_bones, bosh_repel_bones) = bosh::default_bones(points)
             .iter()
             .partition(|bone| matches!(bone.bone_type, BoneType::Normal));
 
-        vec![
+        [
             sled::default_bones(points),
             default_sled_mounter_bones(points),
             bosh_normal_bones,
@@ -143,23 +222,29 @@ pub mod boshsled {
     }
 
     // TODO - precompute resting lengths of bones
-    pub fn default_sled_mounter_bones(points: &HashMap<PointIndex, EntityPoint>) -> Vec<Bone> {
+    pub fn default_sled_mounter_bones(points: &EntityPoints) -> Vec<Bone> {
         make_bones(
             vec![
                 (
                     PointIndex::SledPeg,
                     PointIndex::BoshButt,
-                    BoneType::Mount { endurance: 0.057 },
+                    BoneType::Mount {
+                        endurance: DEFAULT_MOUNT_ENDURANCE,
+                    },
                 ),
                 (
                     PointIndex::SledTail,
                     PointIndex::BoshButt,
-                    BoneType::Mount { endurance: 0.057 },
+                    BoneType::Mount {
+                        endurance: DEFAULT_MOUNT_ENDURANCE,
+                    },
                 ),
                 (
                     PointIndex::SledNose,
                     PointIndex::BoshButt,
-                    BoneType::Mount { endurance: 0.057 },
+                    BoneType::Mount {
+                        endurance: DEFAULT_MOUNT_ENDURANCE,
+                    },
                 ),
             ],
             points,
@@ -180,33 +265,43 @@ pub mod boshsled {
     }
 
     // TODO - precompute resting lengths of bones
-    fn default_bosh_mounter_bones(points: &HashMap<PointIndex, EntityPoint>) -> Vec<Bone> {
+    fn default_bosh_mounter_bones(points: &EntityPoints) -> Vec<Bone> {
         make_bones(
             vec![
                 (
                     PointIndex::BoshShoulder,
                     PointIndex::SledPeg,
-                    BoneType::Mount { endurance: 0.057 },
+                    BoneType::Mount {
+                        endurance: DEFAULT_MOUNT_ENDURANCE,
+                    },
                 ),
                 (
                     PointIndex::SledRope,
                     PointIndex::BoshLeftHand,
-                    BoneType::Mount { endurance: 0.057 },
+                    BoneType::Mount {
+                        endurance: DEFAULT_MOUNT_ENDURANCE,
+                    },
                 ),
                 (
                     PointIndex::SledRope,
                     PointIndex::BoshRightHand,
-                    BoneType::Mount { endurance: 0.057 },
+                    BoneType::Mount {
+                        endurance: DEFAULT_MOUNT_ENDURANCE,
+                    },
                 ),
                 (
                     PointIndex::BoshLeftFoot,
                     PointIndex::SledNose,
-                    BoneType::Mount { endurance: 0.057 },
+                    BoneType::Mount {
+                        endurance: DEFAULT_MOUNT_ENDURANCE,
+                    },
                 ),
                 (
                     PointIndex::BoshRightFoot,
                     PointIndex::SledNose,
-                    BoneType::Mount { endurance: 0.057 },
+                    BoneType::Mount {
+                        endurance: DEFAULT_MOUNT_ENDURANCE,
+                    },
                 ),
             ],
             points,
@@ -219,7 +314,7 @@ pub mod bosh {
 
     pub const DEFAULT_BONE_COUNT: usize = 8;
 
-    pub fn default_points() -> HashMap<PointIndex, EntityPoint> {
+    pub fn default_points() -> EntityPoints {
         let left_foot = make_entity_point(Vector2D(10.0, 5.0), 0.0);
         let right_foot = make_entity_point(Vector2D(10.0, 5.0), 0.0);
         let left_hand = make_entity_point(Vector2D(11.5, -5.0), 0.1);
@@ -227,7 +322,7 @@ pub mod bosh {
         let shoulder = make_entity_point(Vector2D(5.0, -5.5), 0.8);
         let butt = make_entity_point(Vector2D(5.0, 0.0), 0.8);
 
-        HashMap::from([
+        EntityPoints::from([
             (PointIndex::BoshLeftFoot, left_foot),
             (PointIndex::BoshRightFoot, right_foot),
             (PointIndex::BoshLeftHand, left_hand),
@@ -238,7 +333,7 @@ pub mod bosh {
     }
 
     // TODO - precompute resting lengths of bones
-    pub fn default_bones(points: &HashMap<PointIndex, EntityPoint>) -> Vec<Bone> {
+    pub fn default_bones(points: &EntityPoints) -> Vec<Bone> {
         make_bones(
             vec![
                 (
@@ -292,13 +387,13 @@ mod sled {
 
     pub const DEFAULT_BONE_COUNT: usize = 6;
 
-    pub fn default_points() -> HashMap<PointIndex, EntityPoint> {
+    pub fn default_points() -> EntityPoints {
         let peg = make_entity_point(Vector2D(0.0, 0.0), 0.8);
         let nose = make_entity_point(Vector2D(15.0, 5.0), 0.0);
         let tail = make_entity_point(Vector2D(0.0, 5.0), 0.0);
         let rope = make_entity_point(Vector2D(17.5, 0.0), 0.0);
 
-        HashMap::from([
+        EntityPoints::from([
             (PointIndex::SledPeg, peg),
             (PointIndex::SledNose, nose),
             (PointIndex::SledTail, tail),
@@ -306,7 +401,7 @@ mod sled {
         ])
     }
 
-    pub fn default_bones(points: &HashMap<PointIndex, EntityPoint>) -> Vec<Bone> {
+    pub fn default_bones(points: &EntityPoints) -> Vec<Bone> {
         make_bones(
             vec![
                 (PointIndex::SledPeg, PointIndex::SledTail, BoneType::Normal),
@@ -323,10 +418,35 @@ mod sled {
 
 // ==== PRIVATE UTIL FUNCTIONS ====
 
+/// Returns an error if a bone or joint refers to a point for which `has_point` is false.
+pub(crate) fn check_layout(
+    bones: &[Bone],
+    joints: &[Joint],
+    has_point: impl Fn(PointIndex) -> bool,
+) -> Result<()> {
+    let missing_point = bones
+        .iter()
+        .flat_map(|bone| [bone.p1, bone.p2])
+        .chain(
+            joints
+                .iter()
+                .flat_map(|joint| [joint.pair1.0, joint.pair1.1, joint.pair2.0, joint.pair2.1]),
+        )
+        .find(|index| !has_point(*index));
+    if let Some(index) = missing_point {
+        bail!(
+            "entity layout refers to point {:?}, which it does not have",
+            index
+        );
+    }
+
+    Ok(())
+}
+
 fn make_entity_point(loc: Vector2D, friction: f64) -> EntityPoint {
     EntityPoint {
-        previous_location: loc - Vector2D(0.4, 0.0),
-        momentum: Vector2D(0.4, 0.0),
+        previous_location: loc - DEFAULT_START_VELOCITY,
+        momentum: DEFAULT_START_VELOCITY,
         location: loc,
         friction,
     }
@@ -334,7 +454,7 @@ fn make_entity_point(loc: Vector2D, friction: f64) -> EntityPoint {
 
 fn make_bones(
     bones: Vec<(PointIndex, PointIndex, BoneType)>,
-    point_map: &HashMap<PointIndex, EntityPoint>,
+    point_map: &EntityPoints,
 ) -> Vec<Bone> {
     bones
         .iter()
@@ -347,12 +467,8 @@ fn make_bones(
         .collect()
 }
 
-fn length_between(
-    p1: &PointIndex,
-    p2: &PointIndex,
-    point_map: &HashMap<PointIndex, EntityPoint>,
-) -> f64 {
-    (point_map.get(p2).expect("").location - point_map.get(p1).expect("").location)
+fn length_between(p1: &PointIndex, p2: &PointIndex, point_map: &EntityPoints) -> f64 {
+    (point_map[p2].location - point_map[p1].location)
         .length_squared()
         .sqrt()
 }
@@ -1,7 +1,9 @@
 mod bone;
 mod entities;
+mod options;
 mod point;
 
 pub use bone::*;
 pub use entities::*;
+pub use options::*;
 pub use point::*;
@@ -0,0 +1,31 @@
+use serde::{Deserialize, Serialize};
+
+/// The endurance of the default rider's mount bones.
+pub const DEFAULT_MOUNT_ENDURANCE: f64 = 0.057;
+
+/// Physics settings for a single rider. Every entity a rider splits into keeps its options.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RiderOptions {
+    /// Whether bosh may get back on the sled after falling off.
+    pub remountable: bool,
+    /// Multiplies the gravity applied to the rider.
+    pub gravity_scale: f64,
+    /// Multiplies the endurance stored on each mount bone, which is how far the bone may
+    /// stretch, relative to its length, before bosh falls off.
+    pub mount_endurance_scale: f64,
+    /// Disables crashing: mount bones never break and bosh never falls off the sled.
+    /// Useful for seeing where lines would have taken a rider.
+    pub invincible: bool,
+}
+
+impl Default for RiderOptions {
+    fn default() -> Self {
+        RiderOptions {
+            remountable: false,
+            gravity_scale: 1.0,
+            mount_endurance_scale: 1.0,
+            invincible: false,
+        }
+    }
+}
@@ -1,5 +1,11 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::iter::{FilterMap, Zip};
+use std::ops::Index;
+use std::{array, slice};
+
 use crate::Vector2D;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 #[derive(Serialize, Deserialize, Hash, Debug, PartialEq, Eq, Ord, PartialOrd, Copy, Clone)]
 pub enum PointIndex {
@@ -31,3 +37,222 @@ pub struct EntityPoint {
     pub momentum: Vector2D,
     pub friction: f64,
 }
+
+/// The number of points that an entity can have.
+pub const POINT_COUNT: usize = 10;
+
+impl PointIndex {
+    /// Every point index, in order.
+    pub const ALL: [PointIndex; POINT_COUNT] = [
+        PointIndex::BoshLeftFoot,
+        PointIndex::BoshRightFoot,
+        PointIndex::BoshLeftHand,
+        PointIndex::BoshRightHand,
+        PointIndex::BoshShoulder,
+        PointIndex::BoshButt,
+        PointIndex::SledPeg,
+        PointIndex::SledTail,
+        PointIndex::SledNose,
+        PointIndex::SledRope,
+    ];
+
+    /// Where the point is stored in [`EntityPoints`].
+    pub const fn slot(self) -> usize {
+        self as usize
+    }
+}
+
+/// The points of an entity, with a fixed slot for every [`PointIndex`], so that finding
+/// the points of a bone is an array access rather than a hash lookup. Behaves like a map
+/// from point indices to points, and serializes as one.
+///
+/// Entities used to store their points in a `HashMap<PointIndex, EntityPoint>`. The map
+/// methods that code used, such as `get`, `insert`, `iter` and `values_mut`, work the same
+/// here, and `From` converts to and from such a map for code that needs one.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct EntityPoints {
+    slots: [Option<EntityPoint>; POINT_COUNT],
+}
+
+type Iter<'a> = FilterMap<
+    Zip<slice::Iter<'a, PointIndex>, slice::Iter<'a, Option<EntityPoint>>>,
+    fn((&'a PointIndex, &'a Option<EntityPoint>)) -> Option<(&'a PointIndex, &'a EntityPoint)>,
+>;
+
+type IntoIter = FilterMap<
+    Zip<
+        array::IntoIter<PointIndex, POINT_COUNT>,
+        array::IntoIter<Option<EntityPoint>, POINT_COUNT>,
+    >,
+    fn((PointIndex, Option<EntityPoint>)) -> Option<(PointIndex, EntityPoint)>,
+>;
+
+impl EntityPoints {
+    pub fn new() -> EntityPoints {
+        Default::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.iter().flatten().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.iter().all(Option::is_none)
+    }
+
+    pub fn get(&self, index: &PointIndex) -> Option<&EntityPoint> {
+        self.slots[index.slot()].as_ref()
+    }
+
+    pub fn get_mut(&mut self, index: &PointIndex) -> Option<&mut EntityPoint> {
+        self.slots[index.slot()].as_mut()
+    }
+
+    /// Gets the point stored in `slot`, see [`PointIndex::slot`].
+    pub(crate) fn at_slot_mut(&mut self, slot: usize) -> Option<&mut EntityPoint> {
+        self.slots[slot].as_mut()
+    }
+
+    pub fn contains_key(&self, index: &PointIndex) -> bool {
+        self.slots[index.slot()].is_some()
+    }
+
+    /// Sets the point at `index`, returning the point that was there before.
+    pub fn insert(&mut self, index: PointIndex, point: EntityPoint) -> Option<EntityPoint> {
+        self.slots[index.slot()].replace(point)
+    }
+
+    pub fn remove(&mut self, index: &PointIndex) -> Option<EntityPoint> {
+        self.slots[index.slot()].take()
+    }
+
+    /// Keeps only the points for which `keep` returns true.
+    pub fn retain<F: FnMut(&PointIndex, &mut EntityPoint) -> bool>(&mut self, mut keep: F) {
+        for (index, slot) in PointIndex::ALL.iter().zip(&mut self.slots) {
+            if let Some(point) = slot {
+                if !keep(index, point) {
+                    *slot = None;
+                }
+            }
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        PointIndex::ALL
+            .iter()
+            .zip(&self.slots)
+            .filter_map(occupied as fn(_) -> _)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &PointIndex> + '_ {
+        self.iter().map(|(index, _)| index)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &EntityPoint> + '_ {
+        self.slots.iter().flatten()
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut EntityPoint> + '_ {
+        self.slots.iter_mut().flatten()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&PointIndex, &mut EntityPoint)> + '_ {
+        PointIndex::ALL
+            .iter()
+            .zip(&mut self.slots)
+            .filter_map(|(index, slot)| Some((index, slot.as_mut()?)))
+    }
+}
+
+fn occupied<'a>(
+    (index, slot): (&'a PointIndex, &'a Option<EntityPoint>),
+) -> Option<(&'a PointIndex, &'a EntityPoint)> {
+    Some((index, slot.as_ref()?))
+}
+
+fn occupied_owned(
+    (index, slot): (PointIndex, Option<EntityPoint>),
+) -> Option<(PointIndex, EntityPoint)> {
+    Some((index, slot?))
+}
+
+impl Index<&PointIndex> for EntityPoints {
+    type Output = EntityPoint;
+
+    fn index(&self, index: &PointIndex) -> &EntityPoint {
+        self.get(index)
+            .unwrap_or_else(|| panic!("invalid index {index:?}"))
+    }
+}
+
+impl<'a> IntoIterator for &'a EntityPoints {
+    type Item = (&'a PointIndex, &'a EntityPoint);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+impl IntoIterator for EntityPoints {
+    type Item = (PointIndex, EntityPoint);
+    type IntoIter = IntoIter;
+
+    fn into_iter(self) -> IntoIter {
+        PointIndex::ALL
+            .into_iter()
+            .zip(self.slots)
+            .filter_map(occupied_owned as fn(_) -> _)
+    }
+}
+
+impl FromIterator<(PointIndex, EntityPoint)> for EntityPoints {
+    fn from_iter<T: IntoIterator<Item = (PointIndex, EntityPoint)>>(iter: T) -> Self {
+        let mut points = EntityPoints::new();
+        for (index, point) in iter {
+            points.insert(index, point);
+        }
+
+        points
+    }
+}
+
+impl<const N: usize> From<[(PointIndex, EntityPoint); N]> for EntityPoints {
+    fn from(points: [(PointIndex, EntityPoint); N]) -> Self {
+        points.into_iter().collect()
+    }
+}
+
+impl From<HashMap<PointIndex, EntityPoint>> for EntityPoints {
+    fn from(points: HashMap<PointIndex, EntityPoint>) -> Self {
+        points.into_iter().collect()
+    }
+}
+
+impl From<EntityPoints> for HashMap<PointIndex, EntityPoint> {
+    fn from(points: EntityPoints) -> Self {
+        points.into_iter().collect()
+    }
+}
+
+impl fmt::Debug for EntityPoints {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl Serialize for EntityPoints {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for EntityPoints {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(
+            BTreeMap::<PointIndex, EntityPoint>::deserialize(deserializer)?
+                .into_iter()
+                .collect(),
+        )
+    }
+}